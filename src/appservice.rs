@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use eyre::{bail, Context as _};
+use matrix_sdk::{
+    config::RequestConfig,
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    ruma::{
+        events::{room::member::MembershipState, AnyStateEvent, AnyTimelineEvent, StateEvent},
+        serde::Raw,
+        OwnedDeviceId, OwnedUserId, UserId,
+    },
+    Client, SessionMeta,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+
+use crate::{config::Config, consts::MATRIX_USER_ID, context::Context};
+
+static DEVICE_ID: &str = "RELAYER";
+
+/// Registration of the relayer on the homeserver, see
+/// https://spec.matrix.org/v1.10/application-service-api/#registration
+#[derive(Debug, Serialize)]
+struct Registration {
+    id: String,
+    url: String,
+    as_token: String,
+    hs_token: String,
+    sender_localpart: String,
+    rate_limited: bool,
+    namespaces: Namespaces,
+}
+
+#[derive(Debug, Serialize)]
+struct Namespaces {
+    users: Vec<Namespace>,
+    aliases: Vec<Namespace>,
+    rooms: Vec<Namespace>,
+}
+
+#[derive(Debug, Serialize)]
+struct Namespace {
+    exclusive: bool,
+    regex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transaction {
+    events: Vec<Raw<AnyTimelineEvent>>,
+}
+
+/// Writes the registration YAML to `registration_path`, which has to be added to the
+/// `app_service_config_files` of the homeserver.
+pub fn write_registration(cfg: &Config) -> eyre::Result<()> {
+    if cfg.as_token.is_empty() || cfg.hs_token.is_empty() {
+        bail!("Both \"as_token\" and \"hs_token\" are required in the appservice mode");
+    }
+
+    let server_name = regex_escape(&cfg.server_name());
+
+    let registration = Registration {
+        id: cfg.appservice_id.clone(),
+        url: cfg.appservice_url.clone(),
+        as_token: cfg.as_token.clone(),
+        hs_token: cfg.hs_token.clone(),
+        sender_localpart: MATRIX_USER_ID.to_owned(),
+        rate_limited: false,
+        namespaces: Namespaces {
+            users: vec![
+                Namespace {
+                    exclusive: true,
+                    regex: format!("@{}:{server_name}", regex_escape(MATRIX_USER_ID)),
+                },
+                // Members are registered by the front-end, the relayer only masquerades as them
+                Namespace {
+                    exclusive: false,
+                    regex: format!("@[a-z0-9-]+/.+:{server_name}"),
+                },
+            ],
+            aliases: vec![],
+            rooms: vec![],
+        },
+    };
+
+    let yaml = serde_yaml::to_string(&registration)
+        .wrap_err("Failed to serialize appservice registration")?;
+
+    std::fs::write(&cfg.registration_path, yaml).wrap_err_with(|| {
+        format!(
+            "Failed to write appservice registration to \"{}\"",
+            cfg.registration_path
+        )
+    })?;
+
+    tracing::info!(
+        path = cfg.registration_path,
+        "Written appservice registration"
+    );
+
+    Ok(())
+}
+
+pub fn relayer_id(cfg: &Config) -> eyre::Result<OwnedUserId> {
+    let user_id = format!("@{MATRIX_USER_ID}:{}", cfg.server_name());
+    UserId::parse(&user_id).wrap_err_with(|| format!("Failed to parse relayer id: {user_id}"))
+}
+
+/// Session of the user authorized with the `as_token`, no login is needed in the appservice mode.
+pub fn session(cfg: &Config, user_id: OwnedUserId) -> MatrixSession {
+    MatrixSession {
+        meta: SessionMeta {
+            user_id,
+            device_id: OwnedDeviceId::from(DEVICE_ID),
+        },
+        tokens: MatrixSessionTokens {
+            access_token: cfg.as_token.clone(),
+            refresh_token: None,
+        },
+    }
+}
+
+/// Returns the client which acts on behalf of the user, the user has to be in the namespaces of
+/// the registration.
+pub async fn masquerade(ctx: &Context, user_id: &UserId) -> eyre::Result<Client> {
+    let cfg = ctx.config();
+
+    let client = Client::builder()
+        .homeserver_url(cfg.matrix_url.clone())
+        .request_config(RequestConfig::new().assert_identity())
+        .build()
+        .await
+        .wrap_err("Failed to create matrix client")?;
+
+    client
+        .restore_session(session(&cfg, user_id.to_owned()))
+        .await
+        .wrap_err_with(|| format!("Failed to masquerade as \"{user_id}\""))?;
+
+    Ok(client)
+}
+
+/// Serves the application service API, which the homeserver pushes the events to.
+pub async fn run(ctx: Arc<Context>) -> eyre::Result<()> {
+    let cfg = ctx.config();
+
+    if !cfg.appservice_mode {
+        ctx.shutdown().cancelled().await;
+        return Ok(());
+    }
+
+    let app = router(ctx.clone());
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", cfg.appservice_port))
+        .await
+        .wrap_err_with(|| format!("Failed to bind appservice port {}", cfg.appservice_port))?;
+
+    tracing::info!(port = cfg.appservice_port, "Starting appservice...");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(ctx.shutdown().clone().cancelled_owned())
+        .await
+        .wrap_err("Appservice server has failed")
+}
+
+fn router(ctx: Arc<Context>) -> Router {
+    Router::new()
+        .route("/_matrix/app/v1/transactions/:txn_id", put(push_events))
+        .route("/_matrix/app/v1/users/:user_id", get(not_found))
+        .route("/_matrix/app/v1/rooms/:room_alias", get(not_found))
+        .route("/_matrix/app/v1/ping", post(ping))
+        .layer(middleware::from_fn_with_state(ctx.clone(), authorize))
+        .with_state(ctx)
+}
+
+async fn authorize(State(ctx): State<Arc<Context>>, req: Request, next: Next) -> Response {
+    let header_token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // Older homeservers send the token as a query parameter
+    let query_token = req.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+    });
+
+    match header_token.or(query_token) {
+        Some(token) if bool::from(token.as_bytes().ct_eq(ctx.config().hs_token.as_bytes())) => {
+            next.run(req).await
+        }
+        Some(_) => matrix_error(StatusCode::FORBIDDEN, "M_FORBIDDEN", "Invalid hs_token"),
+        None => matrix_error(
+            StatusCode::UNAUTHORIZED,
+            "M_UNAUTHORIZED",
+            "Missing hs_token",
+        ),
+    }
+}
+
+/// Joins the rooms the relayer is invited to, the homeserver pushes the invite only after it is
+/// persisted, so unlike the sync there is no need to retry the join.
+async fn push_events(
+    State(ctx): State<Arc<Context>>,
+    Path(txn_id): Path<String>,
+    Json(txn): Json<Transaction>,
+) -> Response {
+    let matrix = ctx.matrix();
+    let Some(relayer_id) = matrix.user_id() else {
+        return matrix_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "M_UNKNOWN",
+            "Relayer is not logged in yet",
+        );
+    };
+
+    tracing::debug!(
+        txn_id,
+        "Got {} event(s) from the homeserver",
+        txn.events.len()
+    );
+
+    for event in txn.events {
+        let event = match event.deserialize() {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::debug!(txn_id, err = e.to_string(), "Skipping unknown event");
+                continue;
+            }
+        };
+
+        let AnyTimelineEvent::State(AnyStateEvent::RoomMember(StateEvent::Original(member))) =
+            event
+        else {
+            continue;
+        };
+
+        if member.content.membership != MembershipState::Invite || member.state_key != relayer_id {
+            continue;
+        }
+
+        let room_id = member.room_id.to_string();
+
+        match matrix.join_room_by_id(&member.room_id).await {
+            Ok(_) => tracing::info!(txn_id, room_id, "Successfully joined room"),
+            // Not retried, the homeserver would block all of the following transactions
+            Err(e) => tracing::error!(txn_id, room_id, err = e.to_string(), "Can't join room"),
+        }
+    }
+
+    Json(json!({})).into_response()
+}
+
+async fn ping() -> Response {
+    Json(json!({})).into_response()
+}
+
+async fn not_found() -> Response {
+    matrix_error(StatusCode::NOT_FOUND, "M_NOT_FOUND", "Not found")
+}
+
+fn matrix_error(status: StatusCode, errcode: &str, error: &str) -> Response {
+    (status, Json(json!({ "errcode": errcode, "error": error }))).into_response()
+}
+
+fn regex_escape(s: &str) -> String {
+    s.replace('.', "\\.")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde_json::Value;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        icp::mock::MockIcp,
+        testing::{self, Homeserver},
+    };
+
+    const HS_TOKEN: &str = "hs_token";
+
+    fn config(dir: &TempDir) -> Config {
+        let mut cfg = testing::config(dir);
+        cfg.appservice_mode = true;
+        cfg.as_token = "as_token".to_owned();
+        cfg.hs_token = HS_TOKEN.to_owned();
+        cfg
+    }
+
+    async fn send(
+        app: Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> StatusCode {
+        let mut req = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let req = req.body(Body::from(body.to_string())).unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(config(&dir), Arc::new(MockIcp::new(0))).await;
+        let app = router(ctx);
+        let ping = "/_matrix/app/v1/ping";
+
+        let status = send(app.clone(), "POST", ping, None, json!({})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let status = send(app.clone(), "POST", ping, Some("wrong"), json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = send(app.clone(), "POST", ping, Some(HS_TOKEN), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let uri = format!("{ping}?access_token={HS_TOKEN}");
+        let status = send(app, "POST", &uri, None, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_write_registration() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = config(&dir);
+        cfg.registration_path = dir.path().join("registration.yaml").display().to_string();
+
+        write_registration(&cfg).unwrap();
+
+        let yaml = std::fs::read_to_string(&cfg.registration_path).unwrap();
+        let registration: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(registration["as_token"].as_str(), Some("as_token"));
+        assert_eq!(registration["hs_token"].as_str(), Some(HS_TOKEN));
+        assert_eq!(
+            registration["sender_localpart"].as_str(),
+            Some(MATRIX_USER_ID)
+        );
+
+        let users = registration["namespaces"]["users"].as_sequence().unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0]["exclusive"].as_bool(), Some(true));
+        assert_eq!(users[1]["exclusive"].as_bool(), Some(false));
+        assert_eq!(users[1]["regex"].as_str(), Some("@[a-z0-9-]+/.+:localhost"));
+
+        cfg.hs_token = String::new();
+        assert!(write_registration(&cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_push_events_joins_invited_room() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        homeserver.sync(&[], &[]).await;
+
+        let ctx =
+            testing::synced_context(&homeserver, config(&dir), Arc::new(MockIcp::new(0))).await;
+
+        let invite = |room_id: String, user_id: String| {
+            json!({
+                "type": "m.room.member",
+                "state_key": user_id,
+                "sender": format!("@admin:{}", homeserver.server_name()),
+                "content": { "membership": "invite" },
+                "room_id": room_id,
+                "event_id": format!("$invite{room_id}"),
+                "origin_server_ts": 0,
+            })
+        };
+
+        // Only the invite of the relayer is accepted
+        let txn = json!({
+            "events": [
+                invite(homeserver.room_id("general"), homeserver.relayer_id()),
+                invite(homeserver.room_id("news"), format!("@bob:{}", homeserver.server_name())),
+            ],
+        });
+
+        let uri = "/_matrix/app/v1/transactions/1";
+        let status = send(router(ctx), "PUT", uri, Some(HS_TOKEN), txn).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(
+            homeserver.joined_rooms().await,
+            vec![homeserver.room_id("general")]
+        );
+    }
+}
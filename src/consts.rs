@@ -1,2 +1,5 @@
 pub static HISTORY_POINT_KEY: &str = "history_point";
+pub static GROUP_SPACES_KEY: &str = "group_spaces";
+pub static MEDIA_KEY: &str = "media";
+pub static MATRIX_SESSIONS_KEY: &str = "matrix_sessions";
 pub static MATRIX_USER_ID: &str = "catalyze-relayer-svc";
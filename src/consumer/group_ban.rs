@@ -0,0 +1,125 @@
+use std::{str::FromStr, sync::Arc};
+
+use eyre::Context as _;
+use proxy_types::models::history_event::{
+    GroupMemberBanned, GroupMemberUnbanned, HistoryEvent, HistoryEventEntry, HistoryEventKind,
+};
+
+use super::space::{group_room_ids, join_room_ids};
+use crate::{
+    context::Context,
+    matrix::{ban_member, unban_member},
+    types::MatrixUserID,
+};
+
+static BAN_REASON: &str = "Banned in the group";
+static UNBAN_REASON: &str = "Unbanned in the group";
+
+pub fn group_ban_partition(event: &HistoryEvent) -> eyre::Result<u64> {
+    let kind = HistoryEventKind::from_str(&event.kind)
+        .map_err(|e| eyre::eyre!("Failed to parse history event kind from string: {e}"))?;
+
+    match kind {
+        HistoryEventKind::GroupMemberBanned => {
+            Ok(GroupMemberBanned::try_from(event.clone())?.group_id)
+        }
+        HistoryEventKind::GroupMemberUnbanned => {
+            Ok(GroupMemberUnbanned::try_from(event.clone())?.group_id)
+        }
+        kind => eyre::bail!("Unexpected event kind for the group ban: {kind}"),
+    }
+}
+
+/// Banned and unbanned members share the queue, so a ban is never reversed by an earlier unban.
+pub async fn handle_group_ban(ctx: Arc<Context>, entry: HistoryEventEntry) -> eyre::Result<()> {
+    let kind = HistoryEventKind::from_str(&entry.1.kind)
+        .map_err(|e| eyre::eyre!("Failed to parse history event kind from string: {e}"))?;
+
+    match kind {
+        HistoryEventKind::GroupMemberBanned => handle_group_member_banned(ctx, entry).await,
+        HistoryEventKind::GroupMemberUnbanned => handle_group_member_unbanned(ctx, entry).await,
+        kind => eyre::bail!("Unexpected event kind for the group ban: {kind}"),
+    }
+}
+
+async fn handle_group_member_banned(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupMemberBanned::try_from(event)?;
+
+    let user_id = MatrixUserID::new(
+        payload.principal,
+        payload.username,
+        ctx.config().server_name(),
+    );
+
+    let Some(room_ids) = group_room_ids(ctx.clone(), Some(history_point), payload.group_id).await?
+    else {
+        return Ok(());
+    };
+
+    let mut banned_in = vec![];
+
+    for room_id in room_ids.clone().into_iter().rev() {
+        let banned = ban_member(ctx.clone(), room_id.clone(), user_id.clone(), BAN_REASON)
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to ban member, member: \"{user_id}\", room: \"{room_id}\"")
+            })?;
+
+        if let Some(room) = banned {
+            banned_in.push(room);
+        }
+    }
+
+    tracing::info!(
+        history_point,
+        user_id = user_id.to_string(),
+        room_ids = join_room_ids(&banned_in),
+        "Banned member in the group rooms"
+    );
+
+    Ok(())
+}
+
+async fn handle_group_member_unbanned(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupMemberUnbanned::try_from(event)?;
+
+    let user_id = MatrixUserID::new(
+        payload.principal,
+        payload.username,
+        ctx.config().server_name(),
+    );
+
+    let Some(room_ids) = group_room_ids(ctx.clone(), Some(history_point), payload.group_id).await?
+    else {
+        return Ok(());
+    };
+
+    let mut unbanned_in = vec![];
+
+    for room_id in room_ids.clone().into_iter() {
+        let unbanned = unban_member(ctx.clone(), room_id.clone(), user_id.clone(), UNBAN_REASON)
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to unban member, member: \"{user_id}\", room: \"{room_id}\"")
+            })?;
+
+        if let Some(room) = unbanned {
+            unbanned_in.push(room);
+        }
+    }
+
+    tracing::info!(
+        history_point,
+        user_id = user_id.to_string(),
+        room_ids = join_room_ids(&unbanned_in),
+        "Unbanned member in the group rooms"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use eyre::Context as _;
+use matrix_sdk::ruma::RoomId;
+use proxy_types::models::history_event::{GroupCreated, HistoryEvent, HistoryEventEntry};
+
+use super::space::{get_group, join_room_ids};
+use crate::{
+    context::Context,
+    matrix::{create_space, create_space_room, get_space_room_names},
+};
+
+pub fn group_created_partition(event: &HistoryEvent) -> eyre::Result<u64> {
+    Ok(GroupCreated::try_from(event.clone())?.group_id)
+}
+
+pub async fn handle_group_created(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupCreated::try_from(event)?;
+    let group_id = payload.group_id;
+
+    let Some(group) = get_group(ctx.clone(), Some(history_point), group_id).await? else {
+        return Ok(());
+    };
+
+    if RoomId::parse(group.matrix_space_id.clone()).is_ok() {
+        tracing::info!(
+            history_point,
+            group_id,
+            space_id = group.matrix_space_id,
+            "Skipping event, group already has a space"
+        );
+        return Ok(());
+    }
+
+    let provisioned = ctx
+        .store()
+        .get_group_space(group_id)
+        .await
+        .wrap_err("Failed to get provisioned space of the group")?;
+
+    let (space_id, existing) = match provisioned {
+        // The previous attempt failed after creating the space, so the missing rooms are created
+        Some(space_id) => {
+            let space_id = RoomId::parse(space_id.clone()).wrap_err_with(|| {
+                format!("Failed to parse provisioned space room id: {space_id}")
+            })?;
+
+            let existing = get_space_room_names(ctx.clone(), space_id.clone())
+                .await
+                .wrap_err("Failed to get rooms of the provisioned space")?;
+
+            (space_id, existing)
+        }
+        None => {
+            let topic = Some(group.description.clone()).filter(|d| !d.is_empty());
+
+            let space_id = create_space(ctx.clone(), group.name.clone(), topic)
+                .await
+                .wrap_err_with(|| format!("Failed to create space for the group {group_id}"))?;
+
+            // Saved before creating the rooms, so a retry doesn't create another space
+            ctx.store()
+                .set_group_space(group_id, space_id.to_string())
+                .await
+                .wrap_err("Failed to save provisioned space of the group")?;
+
+            (space_id, vec![])
+        }
+    };
+
+    let missing: Vec<String> = ctx
+        .config()
+        .default_rooms
+        .into_iter()
+        .filter(|name| !existing.contains(name))
+        .collect();
+
+    if missing.is_empty() {
+        tracing::info!(
+            history_point,
+            group_id,
+            space_id = space_id.to_string(),
+            "Skipping event, space is already provisioned for the group"
+        );
+        return Ok(());
+    }
+
+    let mut room_ids = vec![];
+
+    for name in missing {
+        let room_id = create_space_room(ctx.clone(), space_id.clone(), name.clone())
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to create room \"{name}\" for the group {group_id}")
+            })?;
+
+        room_ids.push(room_id);
+    }
+
+    tracing::info!(
+        history_point,
+        group_id,
+        space_id = space_id.to_string(),
+        room_ids = join_room_ids(&room_ids),
+        "Provisioned space for the group"
+    );
+
+    Ok(())
+}
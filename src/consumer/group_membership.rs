@@ -0,0 +1,157 @@
+use std::{str::FromStr, sync::Arc};
+
+use eyre::Context as _;
+use proxy_types::models::history_event::{
+    GroupMemberJoined, GroupMemberLeft, HistoryEvent, HistoryEventEntry, HistoryEventKind,
+};
+
+use super::space::{group_room_ids, join_room_ids};
+use crate::{
+    context::Context,
+    matrix::{accept_invite, invite_member, kick_member},
+    types::MatrixUserID,
+};
+
+static LEAVE_REASON: &str = "Left the group";
+
+pub fn group_membership_partition(event: &HistoryEvent) -> eyre::Result<u64> {
+    let kind = HistoryEventKind::from_str(&event.kind)
+        .map_err(|e| eyre::eyre!("Failed to parse history event kind from string: {e}"))?;
+
+    match kind {
+        HistoryEventKind::GroupMemberJoined => {
+            Ok(GroupMemberJoined::try_from(event.clone())?.group_id)
+        }
+        HistoryEventKind::GroupMemberLeft => Ok(GroupMemberLeft::try_from(event.clone())?.group_id),
+        kind => eyre::bail!("Unexpected event kind for the group membership: {kind}"),
+    }
+}
+
+/// Joined and left members share the queue, so the events of a member are handled in the history
+/// order.
+pub async fn handle_group_membership(
+    ctx: Arc<Context>,
+    entry: HistoryEventEntry,
+) -> eyre::Result<()> {
+    let kind = HistoryEventKind::from_str(&entry.1.kind)
+        .map_err(|e| eyre::eyre!("Failed to parse history event kind from string: {e}"))?;
+
+    match kind {
+        HistoryEventKind::GroupMemberJoined => handle_group_member_joined(ctx, entry).await,
+        HistoryEventKind::GroupMemberLeft => handle_group_member_left(ctx, entry).await,
+        kind => eyre::bail!("Unexpected event kind for the group membership: {kind}"),
+    }
+}
+
+async fn handle_group_member_joined(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupMemberJoined::try_from(event)?;
+
+    let user_id = MatrixUserID::new(
+        payload.principal,
+        payload.username,
+        ctx.config().server_name(),
+    );
+
+    let Some(room_ids) = group_room_ids(ctx.clone(), Some(history_point), payload.group_id).await?
+    else {
+        return Ok(());
+    };
+
+    let mut invited_to = vec![];
+
+    // Space goes first, so the member is able to see the rooms of the space
+    for room_id in room_ids.clone().into_iter() {
+        let invited = invite_member(ctx.clone(), room_id.clone(), user_id.clone())
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to invite member, member: \"{user_id}\", room: \"{room_id}\"")
+            })?;
+
+        if let Some(room) = invited {
+            invited_to.push(room);
+        }
+    }
+
+    tracing::info!(
+        history_point,
+        user_id = user_id.to_string(),
+        room_ids = join_room_ids(&invited_to),
+        "Invited member to the group rooms"
+    );
+
+    if !ctx.config().appservice_mode {
+        return Ok(());
+    }
+
+    // The member is joined right away, there is no invite for the member to accept. Every room is
+    // joined, not only the invited ones, as the invites of a failed attempt are not sent again
+    for room_id in room_ids {
+        if ctx.matrix().get_room(&room_id).is_none() {
+            continue;
+        }
+
+        accept_invite(ctx.clone(), room_id.clone(), user_id.clone())
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to accept invite, member: \"{user_id}\", room: \"{room_id}\"")
+            })?;
+    }
+
+    tracing::info!(
+        history_point,
+        user_id = user_id.to_string(),
+        "Joined member to the group rooms"
+    );
+
+    Ok(())
+}
+
+async fn handle_group_member_left(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupMemberLeft::try_from(event)?;
+
+    let user_id = MatrixUserID::new(
+        payload.principal,
+        payload.username,
+        ctx.config().server_name(),
+    );
+
+    let Some(room_ids) = group_room_ids(ctx.clone(), Some(history_point), payload.group_id).await?
+    else {
+        return Ok(());
+    };
+
+    let mut kicked_from = vec![];
+
+    // Rooms go first, the space is left last so the member can't rejoin its rooms in between
+    for room_id in room_ids.clone().into_iter().rev() {
+        let kicked = kick_member(
+            ctx.clone(),
+            room_id.clone(),
+            user_id.clone(),
+            Some(LEAVE_REASON),
+        )
+        .await
+        .wrap_err_with(|| {
+            format!("Failed to kick member, member: \"{user_id}\", room: \"{room_id}\"")
+        })?;
+
+        if let Some(room) = kicked {
+            kicked_from.push(room);
+        }
+    }
+
+    tracing::info!(
+        history_point,
+        user_id = user_id.to_string(),
+        room_ids = join_room_ids(&kicked_from),
+        "Kicked member from the group rooms"
+    );
+
+    Ok(())
+}
@@ -1,15 +1,19 @@
-use std::{str::FromStr, sync::Arc};
+use std::sync::Arc;
 
 use eyre::Context as _;
-use matrix_sdk::ruma::RoomId;
-use proxy_types::models::history_event::{GroupRoleChanged, HistoryEventEntry};
+use proxy_types::models::history_event::{GroupRoleChanged, HistoryEvent, HistoryEventEntry};
 
+use super::space::{get_group, group_space_id};
 use crate::{
     context::Context,
     matrix::{get_space_rooms, set_member_power_level},
-    types::{MatrixUserID, Role},
+    types::{resolve_power_level, MatrixUserID},
 };
 
+pub fn group_role_partition(event: &HistoryEvent) -> eyre::Result<u64> {
+    Ok(GroupRoleChanged::try_from(event.clone())?.group_id)
+}
+
 pub async fn handle_group_role(
     ctx: Arc<Context>,
     (history_point, event): HistoryEventEntry,
@@ -19,51 +23,27 @@ pub async fn handle_group_role(
     let user_id = MatrixUserID::new(
         payload.principal,
         payload.username,
-        ctx.config().matrix_url.clone(),
+        ctx.config().server_name(),
     );
 
-    let group = ctx.icp().get_group(payload.group_id).await.map_err(|e| {
-        tracing::warn!(
-            history_point,
-            user_id = user_id.to_string(),
-            role = payload.roles[0],
-            error = e.to_string(),
-            group_id = payload.group_id,
-            "Skipping event, failed to get group by id"
-        );
-    });
-    let Ok(group) = group else {
+    let Some(group) = get_group(ctx.clone(), Some(history_point), payload.group_id).await? else {
         return Ok(());
     };
 
-    let space_id = RoomId::parse(group.matrix_space_id.clone())
-        .wrap_err_with(|| format!("Failed to parse space room id: {}", group.matrix_space_id))?;
+    let space_id = group_space_id(ctx.clone(), payload.group_id, &group).await?;
 
-    if payload.roles.len() != 1 {
+    let Some(power_level) = resolve_power_level(&ctx.config(), payload.group_id, &payload.roles)
+    else {
         tracing::warn!(
             history_point,
             user_id = user_id.to_string(),
             roles = payload.roles.join(", "),
-            roles_len = payload.roles.len(),
-            "Skipping event, expected exactly one role"
-        );
-        return Ok(());
-    }
-
-    let role = Role::from_str(&payload.roles[0]).map_err(|e| {
-        tracing::warn!(
-            history_point,
-            user_id = user_id.to_string(),
-            role = payload.roles[0],
-            error = e.to_string(),
-            "Skipping event, failed to parse role"
+            group_id = payload.group_id,
+            "Skipping event, no power level is mapped to the roles"
         );
-    });
-    let Ok(role) = role else {
         return Ok(());
     };
 
-    let power_level = role.power_level();
     let mut room_ids = vec![space_id.clone()];
 
     let space_room_ids = get_space_rooms(ctx.clone(), space_id.clone())
@@ -133,3 +113,137 @@ pub async fn handle_group_role(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use candid::Principal;
+    use proxy_types::models::history_event::HistoryEventKind;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        consumer::{run, QueueKey},
+        icp::mock::{self, MockIcp},
+        testing::{self, Homeserver},
+        types::Group,
+    };
+
+    fn role_changed(group_id: u64) -> HistoryEvent {
+        let payload = GroupRoleChanged {
+            group_id,
+            principal: Principal::anonymous(),
+            username: "alice".to_owned(),
+            roles: vec!["admin".to_owned()],
+        };
+
+        mock::event(HistoryEventKind::GroupRoleChanged, &payload)
+    }
+
+    #[test]
+    fn test_partition_by_group() {
+        assert_eq!(group_role_partition(&role_changed(7)).unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_skips_unknown_group() {
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), Arc::new(MockIcp::new(100))).await;
+
+        // The homeserver is not reachable, so the event is skipped before any matrix request
+        handle_group_role(ctx, (1, role_changed(7))).await.unwrap();
+    }
+
+    async fn is_queue_empty(ctx: &Context, key: &QueueKey) -> bool {
+        ctx.store().queue_len(key.clone()).await.unwrap() == 0
+    }
+
+    /// The group can't be fetched while the IC is down, so the event is kept instead of skipped.
+    #[tokio::test]
+    async fn test_keeps_event_while_icp_is_unavailable() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.max_attempts = 1;
+
+        let icp = Arc::new(MockIcp::new(100));
+        icp.set_unavailable(true);
+
+        let ctx = testing::context(cfg, icp.clone()).await;
+        let key = QueueKey::from(HistoryEventKind::GroupRoleChanged);
+        let entry = (1, role_changed(7));
+
+        let err = handle_group_role(ctx.clone(), entry.clone())
+            .await
+            .unwrap_err();
+        assert!(crate::icp::is_unavailable(&err));
+
+        ctx.store()
+            .commit_events(vec![(key.clone(), entry, Default::default())], 2)
+            .await
+            .unwrap();
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            key.clone(),
+            group_role_partition,
+            Arc::new(handle_group_role),
+        ));
+
+        // A few polls of the consumer, the event is neither acked nor dead-lettered
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ctx.store().queue_len(key.clone()).await.unwrap(), 1);
+        assert_eq!(ctx.store().dead_letter_len(key.clone()).await.unwrap(), 0);
+
+        // The group doesn't exist, so the event is skipped once the IC is back
+        icp.set_unavailable(false);
+        testing::eventually(|| is_queue_empty(&ctx, &key)).await;
+        assert_eq!(ctx.store().dead_letter_len(key).await.unwrap(), 0);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    /// The power level of the role is set in the space and each of its rooms, keeping the power
+    /// level of the relayer.
+    #[tokio::test]
+    async fn test_sets_power_level_in_space_rooms() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let space_id = homeserver.room_id("space");
+        let general_id = homeserver.room_id("general");
+
+        homeserver
+            .sync(&[space_id.clone(), general_id.clone()], &[])
+            .await;
+        homeserver.hierarchy(&space_id, &[general_id.clone()]).await;
+
+        let icp = MockIcp::new(100);
+        icp.set_group(
+            7,
+            Group {
+                matrix_space_id: space_id.clone(),
+                ..Default::default()
+            },
+        );
+
+        let ctx = testing::synced_context(&homeserver, testing::config(&dir), Arc::new(icp)).await;
+        handle_group_role(ctx, (1, role_changed(7))).await.unwrap();
+
+        let member = MatrixUserID::new(
+            Principal::anonymous(),
+            "alice".to_owned(),
+            homeserver.server_name(),
+        );
+
+        let events = homeserver.state_events("m.room.power_levels").await;
+        let mut rooms: Vec<&str> = events.iter().map(|(room_id, _)| room_id.as_str()).collect();
+        rooms.sort();
+        assert_eq!(rooms, vec![general_id.as_str(), space_id.as_str()]);
+
+        for (_, content) in events {
+            assert_eq!(content["users"][member.to_string()], 95);
+            assert_eq!(content["users"][homeserver.relayer_id()], 100);
+        }
+    }
+}
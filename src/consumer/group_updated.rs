@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use eyre::Context as _;
+use proxy_types::models::history_event::{GroupUpdated, HistoryEvent, HistoryEventEntry};
+
+use super::space::{get_group, group_space_id};
+use crate::{
+    context::Context,
+    matrix::{update_space_profile, upload_image, SpaceProfile},
+};
+
+pub fn group_updated_partition(event: &HistoryEvent) -> eyre::Result<u64> {
+    Ok(GroupUpdated::try_from(event.clone())?.group_id)
+}
+
+pub async fn handle_group_updated(
+    ctx: Arc<Context>,
+    (history_point, event): HistoryEventEntry,
+) -> eyre::Result<()> {
+    let payload = GroupUpdated::try_from(event)?;
+    let group_id = payload.group_id;
+
+    let Some(group) = get_group(ctx.clone(), Some(history_point), group_id).await? else {
+        return Ok(());
+    };
+
+    let space_id = group_space_id(ctx.clone(), group_id, &group).await?;
+
+    let avatar_url = match &group.image_url {
+        Some(url) => Some(
+            upload_image(ctx.clone(), url)
+                .await
+                .wrap_err_with(|| format!("Failed to upload avatar of the group {group_id}"))?,
+        ),
+        None => {
+            tracing::debug!(
+                history_point,
+                group_id,
+                "Group image is not an url, skipping avatar update"
+            );
+            None
+        }
+    };
+
+    let profile = SpaceProfile {
+        name: Some(group.name.clone()),
+        topic: Some(group.description.clone()),
+        avatar_url,
+    };
+
+    let updated = update_space_profile(ctx.clone(), space_id.clone(), profile)
+        .await
+        .wrap_err_with(|| format!("Failed to update profile of the space \"{space_id}\""))?;
+
+    tracing::info!(
+        history_point,
+        group_id,
+        space_id = space_id.to_string(),
+        updated = updated
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        "Updated space profile"
+    );
+
+    Ok(())
+}
@@ -2,30 +2,87 @@ use std::{fmt::Display, str::FromStr};
 
 use proxy_types::models::history_event::HistoryEventKind;
 
-#[derive(Debug, Clone)]
+static GROUP_MEMBERSHIP_QUEUE: &str = "GroupMembership";
+static GROUP_BAN_QUEUE: &str = "GroupBan";
+
+/// Queue of the events of one or several kinds. Events which have to be processed in the history
+/// order relative to each other share the queue and its consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct QueueKey {
-    event_kind: HistoryEventKind,
+    name: String,
 }
 
 impl From<HistoryEventKind> for QueueKey {
     fn from(event_kind: HistoryEventKind) -> Self {
-        Self { event_kind }
+        match event_kind {
+            HistoryEventKind::GroupMemberJoined | HistoryEventKind::GroupMemberLeft => {
+                Self::group_membership()
+            }
+            HistoryEventKind::GroupMemberBanned | HistoryEventKind::GroupMemberUnbanned => {
+                Self::group_ban()
+            }
+            event_kind => Self {
+                name: event_kind.to_string(),
+            },
+        }
+    }
+}
+
+impl QueueKey {
+    /// Queue of the joined and left members, so a member who leaves and joins the group again is
+    /// never kicked after being invited.
+    pub fn group_membership() -> Self {
+        Self {
+            name: GROUP_MEMBERSHIP_QUEUE.to_owned(),
+        }
+    }
+
+    /// Queue of the banned and unbanned members, so a ban and its reversal are applied in the
+    /// history order.
+    pub fn group_ban() -> Self {
+        Self {
+            name: GROUP_BAN_QUEUE.to_owned(),
+        }
+    }
+
+    /// Name of the queue, the event kind for the queues of a single kind.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Key of the dead letter queue for the events which handler keeps failing.
+    pub fn dead_letter(&self) -> String {
+        format!("dlq_{}", self.name)
+    }
+
+    /// Key of the hash with the processing attempts of the queued events.
+    pub fn attempts(&self) -> String {
+        format!("attempts_{}", self.name)
     }
 }
 
 impl Display for QueueKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "queue_{}", self.event_kind)
+        write!(f, "queue_{}", self.name)
     }
 }
 
+/// Parses the queue name or the kind of the events of the queue.
 impl FromStr for QueueKey {
     type Err = eyre::Report;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == GROUP_MEMBERSHIP_QUEUE {
+            return Ok(Self::group_membership());
+        }
+
+        if s == GROUP_BAN_QUEUE {
+            return Ok(Self::group_ban());
+        }
+
         let event_kind = HistoryEventKind::from_str(s)
             .map_err(|e| eyre::eyre!("Failed to parse history event kind from string: {e}"))?;
 
-        Ok(Self { event_kind })
+        Ok(Self::from(event_kind))
     }
 }
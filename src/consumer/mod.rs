@@ -1,82 +1,726 @@
-use std::{future::Future, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
 
 use eyre::Context as _;
-use proxy_types::models::history_event::{HistoryEventEntry, HistoryEventKind};
+use proxy_types::models::history_event::{HistoryEvent, HistoryEventEntry, HistoryEventKind};
+use tokio::{sync::mpsc, task::JoinSet};
+use tracing::Instrument;
 
-use crate::{context::Context, data, utils::with_spans};
+use crate::{context::Context, icp, store::QueuedEvent, supervisor, utils::trace};
 
+mod group_ban;
+mod group_created;
+mod group_membership;
 mod group_role_change;
+mod group_updated;
 mod key;
-pub use group_role_change::handle_group_role;
+mod space;
+pub use group_ban::{group_ban_partition, handle_group_ban};
+pub use group_created::{group_created_partition, handle_group_created};
+pub use group_membership::{group_membership_partition, handle_group_membership};
+pub use group_role_change::{group_role_partition, handle_group_role};
+pub use group_updated::{group_updated_partition, handle_group_updated};
 pub use key::QueueKey;
+pub use space::group_room_ids;
+
+/// Returns the group id of the event, events of the same group are processed in the history order.
+///
+/// Events are partitioned by the group only: the events of a user in a group touch the rooms of
+/// that group, so they are ordered by it, while the events of a user in different groups touch
+/// different rooms and don't depend on each other.
+pub type Partition = fn(&HistoryEvent) -> eyre::Result<u64>;
 
 pub fn spawn<F, Fut>(
     ctx: Arc<Context>,
-    target_kind: HistoryEventKind,
+    key: QueueKey,
+    partition: Partition,
     handler: F,
 ) -> tokio::task::JoinHandle<eyre::Result<()>>
 where
     F: Fn(Arc<Context>, HistoryEventEntry) -> Fut + Send + Sync + 'static,
     Fut: Future<Output = eyre::Result<()>> + Send + 'static,
 {
-    tokio::spawn(with_spans(
-        &format!("consumer_{}", target_kind),
-        run(ctx, target_kind, handler),
-    ))
+    let handler = Arc::new(handler);
+
+    supervisor::spawn(
+        ctx.clone(),
+        &format!("consumer_{}", key.name()),
+        move || run(ctx.clone(), key.clone(), partition, handler.clone()),
+    )
 }
 
+/// Polls the queue and hands the events over to the workers by the group id. Each worker
+/// processes the events of its groups one by one, while the queue is polled again as soon as the
+/// events are dispatched, so a slow group only delays the groups of its worker.
 async fn run<F, Fut>(
     ctx: Arc<Context>,
-    target_kind: HistoryEventKind,
-    handler: F,
+    key: QueueKey,
+    partition: Partition,
+    handler: Arc<F>,
 ) -> eyre::Result<()>
 where
     F: Fn(Arc<Context>, HistoryEventEntry) -> Fut + Send + Sync + 'static,
     Fut: Future<Output = eyre::Result<()>> + Send + 'static,
 {
     tracing::info!("Starting...");
+    let workers = ctx.config().workers.max(1);
+
+    ctx.store()
+        .prepare_queue(key.clone())
+        .await
+        .wrap_err("Failed to prepare the queue")?;
+
+    ctx.register_consumer(key.clone());
+
+    // The events left pending by the previous run keep their groups blocked until they are claimed
+    // again, so the newer events of these groups don't overtake them
+    let pending = ctx
+        .store()
+        .get_pending_events(key.clone())
+        .await
+        .wrap_err("Failed to get pending events from the store")?;
+
+    let mut blocked = vec![HashMap::new(); workers];
+
+    for queued in pending {
+        if let Ok(group_id) = partition(&queued.entry.1) {
+            blocked[worker_index(group_id, workers)]
+                .entry(group_id)
+                .or_insert(queued.id);
+        }
+    }
+
+    let in_flight = Arc::new(Mutex::new(HashSet::new()));
+    let mut senders = Vec::with_capacity(workers);
+    let mut set = JoinSet::new();
+
+    for blocked in blocked {
+        let (tx, rx) = mpsc::unbounded_channel();
+        senders.push(tx);
+
+        let worker = Worker {
+            ctx: ctx.clone(),
+            key: key.clone(),
+            partition,
+            handler: handler.clone(),
+            in_flight: in_flight.clone(),
+            blocked,
+        };
+
+        set.spawn(worker.run(rx).in_current_span());
+    }
+
+    // Workers run until their channels are closed, so a finished worker has failed
+    tokio::select! {
+        res = dispatch(&ctx, &key, partition, &senders, &in_flight) => res?,
+        Some(res) = set.join_next() => {
+            res.wrap_err("Consumer worker panicked")??;
+            eyre::bail!("Consumer worker stopped unexpectedly");
+        }
+    }
+
+    // The workers finish the current events, the rest is claimed again after the restart
+    drop(senders);
+
+    while let Some(res) = set.join_next().await {
+        res.wrap_err("Consumer worker panicked")??;
+    }
+
+    tracing::info!("Stopping...");
+    Ok(())
+}
+
+/// Polls the queue until the shutdown, the number of dispatched and not yet processed events is
+/// limited by `limit`.
+async fn dispatch(
+    ctx: &Arc<Context>,
+    key: &QueueKey,
+    partition: Partition,
+    senders: &[mpsc::UnboundedSender<QueuedEvent>],
+    in_flight: &Mutex<HashSet<String>>,
+) -> eyre::Result<()> {
     let interval = Duration::from_millis(ctx.config().interval);
-    let key = QueueKey::from(target_kind.clone());
+    let limit = ctx.config().limit as usize;
 
     loop {
-        let ctx = ctx.clone();
-        tracing::debug!("Trying to get history events from the redis");
+        if ctx.is_shutting_down() {
+            return Ok(());
+        }
+
+        if ctx.is_consumer_paused(key) {
+            tracing::debug!("Consumer is paused, waiting for the next iteration...");
+            ctx.sleep(interval).await;
+            continue;
+        }
 
-        let events = data::get_events(ctx.clone(), key.clone())
+        if lock(in_flight)?.len() >= limit {
+            tracing::debug!("Workers are busy, waiting for the next iteration...");
+            ctx.sleep(interval).await;
+            continue;
+        }
+
+        tracing::debug!("Trying to get history events from the store");
+
+        let events = ctx
+            .store()
+            .get_events(key.clone())
             .await
-            .wrap_err("Failed to get history events from the redis")?;
+            .wrap_err("Failed to get history events from the store")?;
 
         tracing::debug!("Got {} event(s)", events.len());
 
-        if events.is_empty() {
-            tracing::debug!("No events in the queue, waiting for the next iteration...");
-            tokio::time::sleep(interval).await;
+        let mut dispatched = 0;
+
+        for queued in events {
+            // The event is claimed again when it waits for the worker longer than the claim timeout
+            if !lock(in_flight)?.insert(queued.id.clone()) {
+                continue;
+            }
+
+            let group_id = partition(&queued.entry.1).unwrap_or_else(|e| {
+                tracing::warn!(
+                    history_point = queued.entry.0,
+                    error = e.to_string(),
+                    "Failed to get group id of the event, processing it on the first worker"
+                );
+                0
+            });
+
+            senders[worker_index(group_id, senders.len())]
+                .send(queued)
+                .map_err(|_| eyre::eyre!("Consumer worker has stopped"))?;
+
+            dispatched += 1;
+        }
+
+        if dispatched == 0 {
+            tracing::debug!("No new events in the queue, waiting for the next iteration...");
+            ctx.sleep(interval).await;
             continue;
         }
 
-        for (history_point, event) in events.clone() {
-            let ctx = ctx.clone();
+        tracing::debug!("Dispatched {} event(s)", dispatched);
+    }
+}
 
-            let kind = HistoryEventKind::from_str(&event.kind).map_err(|e| {
-                eyre::eyre!(
-                    "Failed to parse history event kind from string during processing events: {e}"
-                )
-            })?;
+/// Events of the same group touch the same rooms, so they always go to the same worker and are
+/// never processed concurrently.
+fn worker_index(group_id: u64, workers: usize) -> usize {
+    (group_id % workers as u64) as usize
+}
 
-            if kind != target_kind.clone() {
-                tracing::warn!(
-                    "Event kind mismatch, expected: {:?}, got: {:?}",
-                    target_kind,
-                    kind
-                );
-                continue;
+fn lock(in_flight: &Mutex<HashSet<String>>) -> eyre::Result<MutexGuard<'_, HashSet<String>>> {
+    in_flight
+        .lock()
+        .map_err(|_| eyre::eyre!("In-flight events mutex is poisoned"))
+}
+
+struct Worker<F> {
+    ctx: Arc<Context>,
+    key: QueueKey,
+    partition: Partition,
+    handler: Arc<F>,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    /// Groups with a failed event and the id of that event. The later events of the group are left
+    /// pending to keep the order, until the failed event is claimed again or leaves the queue, e.g.
+    /// it is acknowledged by another replica which has claimed it.
+    blocked: HashMap<u64, String>,
+}
+
+impl<F, Fut> Worker<F>
+where
+    F: Fn(Arc<Context>, HistoryEventEntry) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    async fn run(mut self, mut events: mpsc::UnboundedReceiver<QueuedEvent>) -> eyre::Result<()> {
+        while let Some(queued) = events.recv().await {
+            let id = queued.id.clone();
+            let res = self.process(queued).await;
+
+            lock(&self.in_flight)?.remove(&id);
+            res?;
+        }
+
+        Ok(())
+    }
+
+    async fn process(&mut self, queued: QueuedEvent) -> eyre::Result<()> {
+        let ctx = self.ctx.clone();
+        let key = self.key.clone();
+        let (history_point, event) = queued.entry.clone();
+
+        // The dispatched events are left pending and are claimed again after the restart
+        if ctx.is_shutting_down() {
+            tracing::debug!(history_point, "Shutting down, leaving the event pending");
+            return Ok(());
+        }
+
+        let kind = HistoryEventKind::from_str(&event.kind).map_err(|e| {
+            eyre::eyre!(
+                "Failed to parse history event kind from string during processing events: {e}"
+            )
+        })?;
+
+        if QueueKey::from(kind.clone()) != key {
+            tracing::warn!(
+                "Event kind mismatch, expected an event of the \"{}\" queue, got: {:?}",
+                key,
+                kind
+            );
+            ctx.store().ack_event(key, &queued.id).await?;
+            return Ok(());
+        }
+
+        let group_id = (self.partition)(&event).ok();
+
+        if let Some(group_id) = group_id {
+            match self.blocked.get(&group_id).cloned() {
+                Some(failed) if failed != queued.id => {
+                    let pending = ctx
+                        .store()
+                        .is_queued(key.clone(), &failed)
+                        .await
+                        .wrap_err("Failed to check the blocking event of the group")?;
+
+                    if pending {
+                        tracing::debug!(
+                            history_point,
+                            group_id,
+                            "Postponing event, previous event of the group is pending"
+                        );
+                        return Ok(());
+                    }
+
+                    tracing::debug!(
+                        history_point,
+                        group_id,
+                        "Previous event of the group has left the queue, unblocking the group"
+                    );
+                    self.blocked.remove(&group_id);
+                }
+                Some(_) => {
+                    self.blocked.remove(&group_id);
+                }
+                None => {}
+            }
+        }
+
+        let span = tracing::info_span!("consume_event", history_point, kind = event.kind);
+        trace::set_parent(&span, &queued.trace);
+
+        let started = Instant::now();
+        let res = (self.handler)(ctx.clone(), (history_point, event.clone()))
+            .instrument(span.clone())
+            .await;
+
+        ctx.metrics()
+            .observe_handler(&kind.to_string(), started.elapsed(), res.is_ok());
+
+        if let Err(err) = res {
+            let id = queued.id.clone();
+            let retried = handle_failure(ctx, key, queued, err)
+                .instrument(span)
+                .await?;
+
+            if let (true, Some(group_id)) = (retried, group_id) {
+                self.blocked.insert(group_id, id);
+            }
+            return Ok(());
+        }
+
+        ctx.store().ack_event(key, &queued.id).await?;
+        tracing::info!(history_point, kind = event.kind, "Processed event");
+
+        Ok(())
+    }
+}
+
+/// Counts the failed attempt of the event. The event stays pending and is claimed again after
+/// the claim timeout, until the attempts limit is reached and it is moved to the dead letter queue.
+/// Returns `true` if the event will be retried.
+async fn handle_failure(
+    ctx: Arc<Context>,
+    key: QueueKey,
+    event: QueuedEvent,
+    err: eyre::Report,
+) -> eyre::Result<bool> {
+    let history_point = event.entry.0;
+    let max_attempts = ctx.config().max_attempts;
+
+    // The event is fine, so the outage of the IC doesn't move it to the dead letter queue
+    if icp::is_unavailable(&err) {
+        tracing::warn!(
+            history_point,
+            error = format!("{err:#}"),
+            "ICP is unavailable, the event will be retried after the claim timeout"
+        );
+        return Ok(true);
+    }
+
+    let attempts = ctx
+        .store()
+        .incr_attempts(key.clone(), &event.id)
+        .await
+        .wrap_err("Failed to count the failed attempt of the event")?;
+
+    if attempts < max_attempts {
+        tracing::warn!(
+            history_point,
+            attempts,
+            max_attempts,
+            error = format!("{err:#}"),
+            "Failed to process event, it will be retried after the claim timeout"
+        );
+        return Ok(true);
+    }
+
+    tracing::error!(
+        history_point,
+        attempts,
+        error = format!("{err:#}"),
+        queue = key.dead_letter(),
+        "Failed to process event, moving it to the dead letter queue"
+    );
+
+    let errors = err.chain().map(|e| e.to_string()).collect();
+
+    ctx.store()
+        .dead_letter(key, event, attempts, errors)
+        .await
+        .wrap_err("Failed to move event to the dead letter queue")?;
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    };
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        icp::{
+            mock::{self, MockIcp},
+            CircuitOpen,
+        },
+        testing,
+    };
+
+    const KIND: HistoryEventKind = HistoryEventKind::GroupCreated;
+
+    fn partition(_: &HistoryEvent) -> eyre::Result<u64> {
+        Ok(0)
+    }
+
+    async fn context_with_events(cfg: Config, events: u64) -> Arc<Context> {
+        let ctx = testing::context(cfg, Arc::new(MockIcp::new(100))).await;
+
+        let batch = (1..=events)
+            .map(|history_point| {
+                let entry = (history_point, mock::event(KIND, &history_point));
+                (QueueKey::from(KIND), entry, Default::default())
+            })
+            .collect();
+
+        ctx.store().commit_events(batch, events + 1).await.unwrap();
+        ctx
+    }
+
+    async fn has_lengths(ctx: &Context, queue: u64, dead_letters: u64) -> bool {
+        let key = QueueKey::from(KIND);
+        ctx.store().queue_len(key.clone()).await.unwrap() == queue
+            && ctx.store().dead_letter_len(key).await.unwrap() == dead_letters
+    }
+
+    #[tokio::test]
+    async fn test_processes_and_acks_events() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context_with_events(testing::config(&dir), 3).await;
+        let processed = Arc::new(Mutex::new(vec![]));
+
+        let handler = {
+            let processed = processed.clone();
+            move |_: Arc<Context>, (history_point, _): HistoryEventEntry| {
+                processed.lock().unwrap().push(history_point);
+                async { eyre::Ok(()) }
+            }
+        };
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![1, 2, 3]);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_after_max_attempts() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.max_attempts = 2;
+
+        let ctx = context_with_events(cfg, 1).await;
+        let handler = |_: Arc<Context>, _: HistoryEventEntry| async { eyre::bail!("Failed") };
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 0, 1)).await;
+
+        let dead_letters = ctx
+            .store()
+            .get_dead_letters(QueueKey::from(KIND), 10)
+            .await
+            .unwrap();
+        assert_eq!(dead_letters[0].attempts, 2);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_icp_outage_does_not_count_attempts() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.max_attempts = 1;
+
+        let ctx = context_with_events(cfg, 1).await;
+        let calls = Arc::new(AtomicU32::new(0));
+
+        let handler = {
+            let calls = calls.clone();
+            move |_: Arc<Context>, _: HistoryEventEntry| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call < 3 {
+                        true => Err(CircuitOpen {
+                            retry_in: Duration::ZERO,
+                        }
+                        .into()),
+                        false => eyre::Ok(()),
+                    }
+                }
             }
+        };
 
-            handler(ctx.clone(), (history_point, event.clone())).await?;
-            data::pop_from_queue(ctx, key.clone()).await?;
-            tracing::info!(history_point, kind = event.kind, "Processed event");
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    fn partition_by_point(event: &HistoryEvent) -> eyre::Result<u64> {
+        Ok(candid::Decode!(&event.data, u64)?)
+    }
+
+    /// Records the processed events, the first attempt of the `failing` event fails.
+    fn recording_handler(
+        processed: Arc<Mutex<Vec<u64>>>,
+        failing: u64,
+    ) -> impl Fn(Arc<Context>, HistoryEventEntry) -> std::future::Ready<eyre::Result<()>>
+           + Send
+           + Sync
+           + 'static {
+        let failed = Arc::new(AtomicU32::new(0));
+
+        move |_: Arc<Context>, (history_point, _): HistoryEventEntry| {
+            if history_point == failing && failed.fetch_add(1, Ordering::SeqCst) == 0 {
+                return std::future::ready(Err(eyre::eyre!("Failed")));
+            }
+
+            processed.lock().unwrap().push(history_point);
+            std::future::ready(Ok(()))
         }
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_blocks_group_across_polls() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.claim_timeout = 50;
+        cfg.limit = 1;
+
+        let ctx = context_with_events(cfg, 2).await;
+        let processed = Arc::new(Mutex::new(vec![]));
+        let handler = recording_handler(processed.clone(), 1);
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![1, 2]);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pending_events_block_group_after_restart() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.claim_timeout = 50;
+
+        let ctx = context_with_events(cfg, 1).await;
+        let key = QueueKey::from(KIND);
+
+        // The event was claimed by the previous run which stopped before acknowledging it
+        ctx.store().get_events(key.clone()).await.unwrap();
+
+        let entry = (2, mock::event(KIND, &2u64));
+        ctx.store()
+            .commit_events(vec![(key, entry, Default::default())], 3)
+            .await
+            .unwrap();
+
+        let processed = Arc::new(Mutex::new(vec![]));
+        let handler = recording_handler(processed.clone(), 0);
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![1, 2]);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    /// Another replica has claimed the failed event and acknowledged it, the group is unblocked.
+    #[tokio::test]
+    async fn test_group_is_unblocked_when_failed_event_is_acked_elsewhere() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.claim_timeout = 50;
+        cfg.max_attempts = 100;
+
+        let ctx = context_with_events(cfg, 2).await;
+        let key = QueueKey::from(KIND);
+        let processed = Arc::new(Mutex::new(vec![]));
+        let failures = Arc::new(AtomicU32::new(0));
+
+        let handler = {
+            let processed = processed.clone();
+            let failures = failures.clone();
+            move |_: Arc<Context>, (history_point, _): HistoryEventEntry| {
+                if history_point == 1 {
+                    failures.fetch_add(1, Ordering::SeqCst);
+                    return std::future::ready(Err(eyre::eyre!("Failed")));
+                }
+
+                processed.lock().unwrap().push(history_point);
+                std::future::ready(Ok(()))
+            }
+        };
+
+        let consumer = tokio::spawn(run(ctx.clone(), key.clone(), partition, Arc::new(handler)));
+
+        testing::eventually(|| async { failures.load(Ordering::SeqCst) > 0 }).await;
+        assert!(processed.lock().unwrap().is_empty());
+
+        let failed = ctx
+            .store()
+            .get_pending_events(key.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|queued| queued.entry.0 == 1)
+            .unwrap();
+        ctx.store().ack_event(key, &failed.id).await.unwrap();
+
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![2]);
+
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_slow_group_does_not_stall_other_workers() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.workers = 2;
+
+        let ctx = context_with_events(cfg, 2).await;
+        let processed = Arc::new(Mutex::new(vec![]));
+        let release = Arc::new(tokio::sync::Notify::new());
+
+        let handler = {
+            let processed = processed.clone();
+            let release = release.clone();
+            move |_: Arc<Context>, (history_point, _): HistoryEventEntry| {
+                let processed = processed.clone();
+                let release = release.clone();
+                async move {
+                    // The event 1 goes to the second worker, the rest of the events to the first one
+                    if history_point == 1 {
+                        release.notified().await;
+                    }
+                    processed.lock().unwrap().push(history_point);
+                    eyre::Ok(())
+                }
+            }
+        };
+
+        let consumer = tokio::spawn(run(
+            ctx.clone(),
+            QueueKey::from(KIND),
+            partition_by_point,
+            Arc::new(handler),
+        ));
+
+        testing::eventually(|| has_lengths(&ctx, 1, 0)).await;
+
+        let entry = (4, mock::event(KIND, &4u64));
+        ctx.store()
+            .commit_events(vec![(QueueKey::from(KIND), entry, Default::default())], 5)
+            .await
+            .unwrap();
+
+        testing::eventually(|| has_lengths(&ctx, 1, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![2, 4]);
+
+        release.notify_one();
+        testing::eventually(|| has_lengths(&ctx, 0, 0)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![2, 4, 1]);
 
-        tracing::info!("Processed {} event(s)", events.len());
+        ctx.shutdown().cancel();
+        consumer.await.unwrap().unwrap();
     }
 }
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use eyre::Context as _;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+
+use crate::{
+    context::Context,
+    matrix::get_space_rooms,
+    types::{is_canister_error, Group},
+};
+
+/// Returns the space of the group followed by its child rooms, `None` if the group is not found.
+/// The `history_point` of the handled event is logged, if there is one.
+pub async fn group_room_ids(
+    ctx: Arc<Context>,
+    history_point: Option<u64>,
+    group_id: u64,
+) -> eyre::Result<Option<Vec<OwnedRoomId>>> {
+    let Some(group) = get_group(ctx.clone(), history_point, group_id).await? else {
+        return Ok(None);
+    };
+
+    let space_id = group_space_id(ctx.clone(), group_id, &group).await?;
+
+    let mut space_room_ids = get_space_rooms(ctx.clone(), space_id.clone())
+        .await
+        .wrap_err("Failed to get space room ids")?;
+
+    space_room_ids.retain(|room_id| room_id != &space_id);
+    space_room_ids.sort();
+    space_room_ids.dedup();
+
+    let mut room_ids = vec![space_id];
+    room_ids.extend(space_room_ids);
+
+    Ok(Some(room_ids))
+}
+
+/// Returns the group, `None` if the proxy canister responds that there is no such group. Other
+/// errors, e.g. of the unavailable IC, are returned, so the event is retried instead of skipped.
+pub async fn get_group(
+    ctx: Arc<Context>,
+    history_point: Option<u64>,
+    group_id: u64,
+) -> eyre::Result<Option<Group>> {
+    match ctx.icp().get_group(group_id).await {
+        Ok(group) => Ok(Some(group)),
+        Err(e) if is_canister_error(&e) => {
+            tracing::warn!(
+                history_point,
+                error = e.to_string(),
+                group_id,
+                "Skipping group, failed to get group by id"
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e).wrap_err_with(|| format!("Failed to get group {group_id}")),
+    }
+}
+
+/// Returns the space of the group, falling back to the space provisioned by the relayer when the
+/// group has no valid space id in the proxy.
+pub async fn group_space_id(
+    ctx: Arc<Context>,
+    group_id: u64,
+    group: &Group,
+) -> eyre::Result<OwnedRoomId> {
+    if let Ok(space_id) = RoomId::parse(group.matrix_space_id.clone()) {
+        return Ok(space_id);
+    }
+
+    let provisioned = ctx
+        .store()
+        .get_group_space(group_id)
+        .await
+        .wrap_err("Failed to get provisioned space of the group")?;
+
+    let Some(space_id) = provisioned else {
+        eyre::bail!("Failed to parse space room id: {}", group.matrix_space_id)
+    };
+
+    RoomId::parse(space_id.clone())
+        .wrap_err_with(|| format!("Failed to parse provisioned space room id: {space_id}"))
+}
+
+pub fn join_room_ids(room_ids: &[OwnedRoomId]) -> String {
+    room_ids
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,35 +1,94 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use eyre::Context as _;
+use tokio_util::sync::CancellationToken;
 
-use crate::{config::Config, icp::ICPClient, matrix};
+use crate::{
+    config::Config,
+    consumer::QueueKey,
+    health::Health,
+    icp::{ICPClient, IcpApi},
+    matrix,
+    metrics::Metrics,
+    store::{self, EventStore},
+};
+
+/// Timeouts of the downloads, e.g. the group images, a stalled server must not hold the event.
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub struct Context {
     cfg: Config,
-    redis_conn: redis::aio::MultiplexedConnection,
+    store: Arc<dyn EventStore>,
     matrix: matrix_sdk::Client,
-    icp: ICPClient,
+    icp: Arc<dyn IcpApi>,
+    http: reqwest::Client,
+    metrics: Metrics,
+    health: Health,
+    consumers: RwLock<Consumers>,
+    shutdown: CancellationToken,
+}
+
+/// Queues of the running consumers and the paused ones, the state is local to the replica.
+#[derive(Default)]
+struct Consumers {
+    keys: Vec<QueueKey>,
+    paused: HashSet<String>,
 }
 
 impl Context {
     pub async fn new(cfg: Config) -> eyre::Result<Arc<Self>> {
-        let redis_conn = redis::Client::open(cfg.redis_url.clone())
-            .wrap_err("Failed to establish connection with redis")?
-            .get_multiplexed_tokio_connection()
-            .await
-            .wrap_err("Failed to get redis connection")?;
+        let metrics = Metrics::new().wrap_err("Failed to create metrics")?;
+        let shutdown = CancellationToken::new();
 
-        let icp = ICPClient::new(cfg.clone())
+        let icp = ICPClient::new(cfg.clone(), metrics.clone(), shutdown.clone())
             .await
             .wrap_err("Failed to create icp client")?;
 
+        Self::build(cfg, metrics, Arc::new(icp), shutdown).await
+    }
+
+    /// Creates the context with the given ICP API, e.g. the mock one in the tests.
+    pub async fn with_icp(
+        cfg: Config,
+        metrics: Metrics,
+        icp: Arc<dyn IcpApi>,
+    ) -> eyre::Result<Arc<Self>> {
+        Self::build(cfg, metrics, icp, CancellationToken::new()).await
+    }
+
+    async fn build(
+        cfg: Config,
+        metrics: Metrics,
+        icp: Arc<dyn IcpApi>,
+        shutdown: CancellationToken,
+    ) -> eyre::Result<Arc<Self>> {
+        let store = store::from_cfg(&cfg)
+            .await
+            .wrap_err("Failed to create event store")?;
+
         let matrix = matrix::client_from_cfg(&cfg).await?;
 
+        let http = reqwest::Client::builder()
+            .connect_timeout(HTTP_CONNECT_TIMEOUT)
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .wrap_err("Failed to create http client")?;
+
         Ok(Arc::new(Self {
             cfg,
-            redis_conn,
+            store,
             matrix,
             icp,
+            http,
+            metrics,
+            health: Health::default(),
+            consumers: RwLock::default(),
+            shutdown,
         }))
     }
 
@@ -37,15 +96,86 @@ impl Context {
         self.cfg.clone()
     }
 
-    pub fn redis(&self) -> redis::aio::MultiplexedConnection {
-        self.redis_conn.clone()
+    pub fn store(&self) -> Arc<dyn EventStore> {
+        self.store.clone()
     }
 
-    pub fn icp(&self) -> &ICPClient {
-        &self.icp
+    pub fn icp(&self) -> &dyn IcpApi {
+        self.icp.as_ref()
     }
 
     pub fn matrix(&self) -> matrix_sdk::Client {
         self.matrix.clone()
     }
+
+    /// Client of the downloads, shared to reuse the connections.
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// Token cancelled on the shutdown signal, the loops have to stop taking new work.
+    pub fn shutdown(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Sleeps for the duration, returns false if it is interrupted by the shutdown.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.shutdown.cancelled() => false,
+        }
+    }
+
+    pub fn register_consumer(&self, key: QueueKey) {
+        let mut consumers = self.consumers.write().expect("Consumers lock is poisoned");
+
+        // The consumer registers itself again after the restart
+        if !consumers
+            .keys
+            .iter()
+            .any(|k| k.to_string() == key.to_string())
+        {
+            consumers.keys.push(key);
+        }
+    }
+
+    pub fn consumer_keys(&self) -> Vec<QueueKey> {
+        let consumers = self.consumers.read().expect("Consumers lock is poisoned");
+        consumers.keys.clone()
+    }
+
+    /// Pauses or resumes the consumer, returns false if there is no consumer of the queue.
+    pub fn set_consumer_paused(&self, key: &QueueKey, paused: bool) -> bool {
+        let mut consumers = self.consumers.write().expect("Consumers lock is poisoned");
+        let key = key.to_string();
+
+        if !consumers.keys.iter().any(|k| k.to_string() == key) {
+            return false;
+        }
+
+        if paused {
+            consumers.paused.insert(key);
+        } else {
+            consumers.paused.remove(&key);
+        }
+
+        true
+    }
+
+    pub fn is_consumer_paused(&self, key: &QueueKey) -> bool {
+        let consumers = self.consumers.read().expect("Consumers lock is poisoned");
+        consumers.paused.contains(&key.to_string())
+    }
 }
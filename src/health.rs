@@ -0,0 +1,93 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// State of the long running loops, which can't be checked from the outside.
+#[derive(Default)]
+pub struct Health {
+    logged_in: AtomicBool,
+    last_sync: Mutex<Option<Instant>>,
+    catchup_progress: Mutex<Option<Instant>>,
+    remote_history_point: Mutex<Option<u64>>,
+}
+
+impl Health {
+    pub fn set_logged_in(&self) {
+        self.logged_in.store(true, Ordering::Relaxed);
+        // The sync starts right after the login, give it the whole timeout for the first response
+        self.touch_sync();
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in.load(Ordering::Relaxed)
+    }
+
+    /// Marks that the matrix sync loop got a response.
+    pub fn touch_sync(&self) {
+        *self.last_sync.lock().expect("Health lock is poisoned") = Some(Instant::now());
+    }
+
+    /// Returns the time since the last sync response, `None` before the login.
+    pub fn since_last_sync(&self) -> Option<Duration> {
+        self.last_sync
+            .lock()
+            .expect("Health lock is poisoned")
+            .map(|last_sync| last_sync.elapsed())
+    }
+
+    pub fn set_catchup(&self, catchup: bool) {
+        let mut progress = self
+            .catchup_progress
+            .lock()
+            .expect("Health lock is poisoned");
+
+        match (catchup, *progress) {
+            (true, None) => *progress = Some(Instant::now()),
+            (false, _) => *progress = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// Marks that the producer advanced the history point or is deliberately waiting for ICP.
+    pub fn touch_producer(&self) {
+        let mut progress = self
+            .catchup_progress
+            .lock()
+            .expect("Health lock is poisoned");
+
+        if progress.is_some() {
+            *progress = Some(Instant::now());
+        }
+    }
+
+    /// Returns the time the producer is in the catchup mode without advancing the history point,
+    /// `None` if it is listening.
+    pub fn catchup_stalled_for(&self) -> Option<Duration> {
+        self.catchup_progress
+            .lock()
+            .expect("Health lock is poisoned")
+            .map(|progress| progress.elapsed())
+    }
+
+    /// Saves the history point of the proxy canister the producer observed, it only grows.
+    pub fn set_remote_history_point(&self, point: u64) {
+        let mut remote = self
+            .remote_history_point
+            .lock()
+            .expect("Health lock is poisoned");
+        *remote = Some(remote.map_or(point, |remote| remote.max(point)));
+    }
+
+    /// Returns the last history point of the proxy canister the producer observed, `None` before
+    /// the producer has started.
+    pub fn remote_history_point(&self) -> Option<u64> {
+        *self
+            .remote_history_point
+            .lock()
+            .expect("Health lock is poisoned")
+    }
+}
@@ -0,0 +1,338 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use eyre::{eyre, Context as _};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use super::ApiError;
+use crate::{consumer::QueueKey, context::Context, reconciler, utils::trace};
+
+const DEFAULT_DEAD_LETTERS_LIMIT: u64 = 100;
+
+#[derive(Debug, Serialize)]
+struct HistoryPoint {
+    local: Option<u64>,
+    remote: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Queue {
+    queue: String,
+    length: u64,
+    dead_letters: u64,
+    paused: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DeadLetter {
+    id: String,
+    history_point: u64,
+    kind: String,
+    attempts: u64,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeadLettersQuery {
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Replayed {
+    history_point: u64,
+    queue: String,
+}
+
+pub fn router(ctx: Arc<Context>) -> Router {
+    Router::new()
+        .route("/history-point", get(history_point))
+        .route("/queues", get(queues))
+        .route("/queues/:kind/dead-letters", get(dead_letters))
+        .route("/queues/:kind/pause", post(pause))
+        .route("/queues/:kind/resume", post(resume))
+        .route("/replay/:history_point", post(replay))
+        .route("/groups/:group_id/reconcile", post(reconcile))
+        .layer(middleware::from_fn_with_state(ctx.clone(), authorize))
+        .with_state(ctx)
+}
+
+async fn authorize(State(ctx): State<Arc<Context>>, req: Request, next: Next) -> Response {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if bool::from(token.as_bytes().ct_eq(ctx.config().admin_token.as_bytes())) => {
+            next.run(req).await
+        }
+        _ => ApiError::new(StatusCode::UNAUTHORIZED, eyre!("Invalid admin token")).into_response(),
+    }
+}
+
+async fn history_point(State(ctx): State<Arc<Context>>) -> Result<Json<HistoryPoint>, ApiError> {
+    let local = ctx
+        .store()
+        .get_history_point()
+        .await
+        .wrap_err("Failed to get history point from the store")?;
+
+    let remote = ctx
+        .icp()
+        .get_history_point()
+        .await
+        .wrap_err("Failed to get history point from ICP")?;
+
+    Ok(Json(HistoryPoint { local, remote }))
+}
+
+async fn queues(State(ctx): State<Arc<Context>>) -> Result<Json<Vec<Queue>>, ApiError> {
+    let mut queues = vec![];
+
+    for key in ctx.consumer_keys() {
+        queues.push(Queue {
+            queue: key.to_string(),
+            length: ctx.store().queue_len(key.clone()).await?,
+            dead_letters: ctx.store().dead_letter_len(key.clone()).await?,
+            paused: ctx.is_consumer_paused(&key),
+        });
+    }
+
+    Ok(Json(queues))
+}
+
+async fn dead_letters(
+    State(ctx): State<Arc<Context>>,
+    Path(kind): Path<String>,
+    Query(query): Query<DeadLettersQuery>,
+) -> Result<Json<Vec<DeadLetter>>, ApiError> {
+    let key = QueueKey::from_str(&kind).map_err(ApiError::bad_request)?;
+    let limit = query.limit.unwrap_or(DEFAULT_DEAD_LETTERS_LIMIT);
+
+    let dead_letters = ctx
+        .store()
+        .get_dead_letters(key, limit)
+        .await?
+        .into_iter()
+        .map(|dead_letter| DeadLetter {
+            id: dead_letter.id,
+            history_point: dead_letter.entry.0,
+            kind: dead_letter.entry.1.kind,
+            attempts: dead_letter.attempts,
+            errors: dead_letter.errors,
+        })
+        .collect();
+
+    Ok(Json(dead_letters))
+}
+
+async fn pause(
+    State(ctx): State<Arc<Context>>,
+    Path(kind): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    set_paused(ctx, kind, true)
+}
+
+async fn resume(
+    State(ctx): State<Arc<Context>>,
+    Path(kind): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    set_paused(ctx, kind, false)
+}
+
+fn set_paused(ctx: Arc<Context>, kind: String, paused: bool) -> Result<StatusCode, ApiError> {
+    let key = QueueKey::from_str(&kind).map_err(ApiError::bad_request)?;
+
+    if !ctx.set_consumer_paused(&key, paused) {
+        return Err(ApiError::not_found(eyre!(
+            "No consumer of the \"{key}\" queue"
+        )));
+    }
+
+    tracing::info!(
+        queue = key.to_string(),
+        paused,
+        "Consumer is paused or resumed by admin"
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Queues the event of the history point again, regardless of the stored history point.
+async fn replay(
+    State(ctx): State<Arc<Context>>,
+    Path(history_point): Path<u64>,
+) -> Result<Json<Replayed>, ApiError> {
+    let entry = ctx
+        .icp()
+        .get_events(history_point)
+        .await
+        .wrap_err_with(|| format!("Failed to get event of the history point {history_point}"))?
+        .into_iter()
+        .find(|(point, _)| *point == history_point)
+        .ok_or_else(|| {
+            ApiError::not_found(eyre!("No event at the history point {history_point}"))
+        })?;
+
+    let key = QueueKey::from_str(&entry.1.kind)?;
+
+    let span = tracing::info_span!("replay_event", history_point, kind = entry.1.kind);
+
+    ctx.store()
+        .queue_event(key.clone(), entry, trace::inject(&span))
+        .await?;
+
+    tracing::info!(
+        history_point,
+        queue = key.to_string(),
+        "Event is replayed by admin"
+    );
+
+    Ok(Json(Replayed {
+        history_point,
+        queue: key.to_string(),
+    }))
+}
+
+async fn reconcile(
+    State(ctx): State<Arc<Context>>,
+    Path(group_id): Path<u64>,
+) -> Result<Json<Vec<reconciler::Drift>>, ApiError> {
+    let drifts = reconciler::reconcile_group(ctx, group_id)
+        .await
+        .wrap_err_with(|| format!("Failed to reconcile group {group_id}"))?;
+
+    Ok(Json(drifts))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use proxy_types::models::history_event::HistoryEventKind;
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{icp::mock::MockIcp, testing};
+
+    const ADMIN_TOKEN: &str = "admin_token";
+
+    async fn context(dir: &TempDir, icp: Arc<MockIcp>) -> Arc<Context> {
+        let mut cfg = testing::config(dir);
+        cfg.admin_token = ADMIN_TOKEN.to_owned();
+        testing::context(cfg, icp).await
+    }
+
+    async fn send(
+        ctx: Arc<Context>,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+    ) -> (StatusCode, Value) {
+        let mut req = axum::http::Request::builder().method(method).uri(uri);
+
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let resp = router(ctx)
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir, Arc::new(MockIcp::new(100))).await;
+
+        let (status, _) = send(ctx.clone(), "GET", "/history-point", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(ctx.clone(), "GET", "/history-point", Some("wrong")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(ctx, "GET", "/history-point", Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_history_point() {
+        let dir = TempDir::new().unwrap();
+        let icp = Arc::new(MockIcp::new(100));
+        icp.push_event(HistoryEventKind::GroupCreated, &1u64);
+        icp.push_event(HistoryEventKind::GroupCreated, &2u64);
+
+        let ctx = context(&dir, icp).await;
+        ctx.store().set_history_point(2).await.unwrap();
+
+        let (status, body) = send(ctx, "GET", "/history-point", Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "local": 2, "remote": 3 }));
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir, Arc::new(MockIcp::new(100))).await;
+
+        let key = QueueKey::from(HistoryEventKind::GroupCreated);
+        ctx.register_consumer(key.clone());
+
+        let uri = format!("/queues/{}/pause", key.name());
+        let (status, _) = send(ctx.clone(), "POST", &uri, Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(ctx.is_consumer_paused(&key));
+
+        let (_, body) = send(ctx.clone(), "GET", "/queues", Some(ADMIN_TOKEN)).await;
+        assert_eq!(body[0]["paused"], json!(true));
+
+        let uri = format!("/queues/{}/resume", key.name());
+        let (status, _) = send(ctx.clone(), "POST", &uri, Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!ctx.is_consumer_paused(&key));
+
+        // No consumer of the queue is running
+        let uri = format!("/queues/{}/pause", QueueKey::group_ban().name());
+        let (status, _) = send(ctx, "POST", &uri, Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = TempDir::new().unwrap();
+        let icp = Arc::new(MockIcp::new(100));
+        let history_point = icp.push_event(HistoryEventKind::GroupCreated, &1u64);
+
+        let ctx = context(&dir, icp).await;
+        let key = QueueKey::from(HistoryEventKind::GroupCreated);
+
+        let uri = format!("/replay/{history_point}");
+        let (status, body) = send(ctx.clone(), "POST", &uri, Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({ "history_point": history_point, "queue": key.to_string() })
+        );
+        assert_eq!(ctx.store().queue_len(key).await.unwrap(), 1);
+
+        let (status, _) = send(ctx, "POST", "/replay/42", Some(ADMIN_TOKEN)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::context::Context;
+
+#[derive(Debug, Serialize)]
+struct Report {
+    ok: bool,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn failed(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+        }
+    }
+}
+
+impl From<eyre::Result<()>> for Check {
+    fn from(res: eyre::Result<()>) -> Self {
+        match res {
+            Ok(()) => Self::ok(),
+            Err(e) => Self::failed(format!("{e:#}")),
+        }
+    }
+}
+
+pub fn router(ctx: Arc<Context>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(ctx)
+}
+
+/// Liveness: the loops of the process are not stuck, restarting helps otherwise. The Matrix sync
+/// is not checked, a restart doesn't help while the homeserver is down.
+async fn healthz(State(ctx): State<Arc<Context>>) -> (StatusCode, Json<Report>) {
+    let mut checks = BTreeMap::new();
+
+    checks.insert("producer", producer(&ctx));
+
+    respond(checks)
+}
+
+/// Readiness: the relayer is logged in, its dependencies are reachable and the Matrix sync is
+/// responding.
+async fn readyz(State(ctx): State<Arc<Context>>) -> (StatusCode, Json<Report>) {
+    let mut checks = BTreeMap::new();
+
+    let login = match ctx.health().is_logged_in() {
+        true => Check::ok(),
+        false => Check::failed("Matrix login is not completed"),
+    };
+
+    checks.insert("matrix_login", login);
+    checks.insert("matrix_sync", matrix_sync(&ctx));
+    checks.insert("producer", producer(&ctx));
+    checks.insert("store", ctx.store().ping().await.into());
+    checks.insert("icp", icp(&ctx));
+
+    respond(checks)
+}
+
+fn matrix_sync(ctx: &Context) -> Check {
+    let timeout = Duration::from_millis(ctx.config().sync_timeout);
+
+    match ctx.health().since_last_sync() {
+        Some(elapsed) if elapsed > timeout => Check::failed(format!(
+            "No matrix sync response for {}s",
+            elapsed.as_secs()
+        )),
+        // Before the login there is no sync yet, the readiness is failed by the login check
+        _ => Check::ok(),
+    }
+}
+
+fn producer(ctx: &Context) -> Check {
+    let timeout = ctx.config().catchup_timeout;
+
+    // A long catchup is fine as long as the history point keeps advancing
+    match ctx.health().catchup_stalled_for() {
+        Some(elapsed) if timeout > 0 && elapsed > Duration::from_millis(timeout) => {
+            Check::failed(format!(
+                "Producer has not advanced the history point in the catchup mode for {}s",
+                elapsed.as_secs()
+            ))
+        }
+        _ => Check::ok(),
+    }
+}
+
+/// Checks what the producer observed instead of calling ICP on every probe.
+fn icp(ctx: &Context) -> Check {
+    if ctx.health().remote_history_point().is_none() {
+        return Check::failed("History point is not received from ICP yet");
+    }
+
+    match ctx.icp().circuit_retry_in() {
+        Some(retry_in) => Check::failed(format!(
+            "ICP circuit is open, retrying in {}s",
+            retry_in.as_secs()
+        )),
+        None => Check::ok(),
+    }
+}
+
+fn respond(checks: BTreeMap<&'static str, Check>) -> (StatusCode, Json<Report>) {
+    let ok = checks.values().all(|check| check.ok);
+
+    let status = match ok {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(Report { ok, checks }))
+}
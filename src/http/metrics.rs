@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use eyre::Context as _;
+
+use super::ApiError;
+use crate::context::Context;
+
+static CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4";
+
+pub fn router(ctx: Arc<Context>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(ctx)
+}
+
+async fn metrics(State(ctx): State<Arc<Context>>) -> Result<Response, ApiError> {
+    if let Err(e) = refresh(ctx.clone()).await {
+        tracing::warn!(err = format!("{e:#}"), "Failed to refresh metrics");
+    }
+
+    let body = ctx.metrics().render()?;
+    Ok(([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], body).into_response())
+}
+
+/// Updates the gauges which are read from the store on scrape, ICP is not called.
+async fn refresh(ctx: Arc<Context>) -> eyre::Result<()> {
+    // The open circuit turns half-open without any call
+    ctx.metrics()
+        .set_icp_circuit_state(ctx.icp().circuit_state());
+
+    for key in ctx.consumer_keys() {
+        let depth = ctx.store().queue_len(key.clone()).await?;
+        let dead_letters = ctx.store().dead_letter_len(key.clone()).await?;
+
+        ctx.metrics()
+            .set_queue_depth(&key.to_string(), depth, dead_letters);
+    }
+
+    let local = ctx
+        .store()
+        .get_history_point()
+        .await
+        .wrap_err("Failed to get history point from the store")?
+        .unwrap_or_default();
+
+    // The producer has not got the history point from ICP yet
+    if let Some(remote) = ctx.health().remote_history_point() {
+        ctx.metrics().set_producer_lag(remote.saturating_sub(local));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use eyre::Context as _;
+use serde_json::json;
+
+use crate::context::Context;
+
+mod admin;
+mod health;
+mod metrics;
+
+/// Serves the HTTP API of the relayer on `http_port`.
+pub async fn run(ctx: Arc<Context>) -> eyre::Result<()> {
+    let cfg = ctx.config();
+    let mut app = metrics::router(ctx.clone()).merge(health::router(ctx.clone()));
+
+    if cfg.admin_token.is_empty() {
+        tracing::warn!("Admin token is not set, admin API is disabled");
+    } else {
+        app = app.nest("/admin", admin::router(ctx.clone()));
+    }
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", cfg.http_port))
+        .await
+        .wrap_err_with(|| format!("Failed to bind HTTP port {}", cfg.http_port))?;
+
+    tracing::info!(port = cfg.http_port, "Starting HTTP server...");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(ctx.shutdown().clone().cancelled_owned())
+        .await
+        .wrap_err("HTTP server has failed")
+}
+
+/// Error of the HTTP handler, responded as JSON with the error chain.
+pub struct ApiError {
+    status: StatusCode,
+    report: eyre::Report,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, report: eyre::Report) -> Self {
+        Self { status, report }
+    }
+
+    pub fn bad_request(report: eyre::Report) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, report)
+    }
+
+    pub fn not_found(report: eyre::Report) -> Self {
+        Self::new(StatusCode::NOT_FOUND, report)
+    }
+}
+
+impl From<eyre::Report> for ApiError {
+    fn from(report: eyre::Report) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, report)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            tracing::error!(
+                err = format!("{:#}", self.report),
+                "HTTP request has failed"
+            );
+        }
+
+        let errors: Vec<String> = self.report.chain().map(|e| e.to_string()).collect();
+        (self.status, Json(json!({ "errors": errors }))).into_response()
+    }
+}
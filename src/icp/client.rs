@@ -0,0 +1,301 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::IcpApi;
+use crate::{
+    config::Config,
+    metrics::Metrics,
+    types::{CanisterResult, Group, GroupMember, GroupsPage},
+    utils::{backoff, CircuitBreaker, CircuitState},
+};
+use candid::{Encode, Principal};
+use eyre::Context;
+use ic_agent::{
+    agent::RejectCode,
+    identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity},
+    AgentError, Identity,
+};
+use proxy_types::models::{
+    group::{GroupFilter, GroupResponse, GroupSort},
+    history_event::HistoryEventEntry,
+    member::JoinedMemberResponse,
+    paged_response::PagedResponse,
+};
+use tokio_util::sync::CancellationToken;
+
+pub struct ICPClient {
+    agent: ic_agent::Agent,
+    proxy_id: Principal,
+    history_id: Principal,
+    limit: u64,
+    metrics: Metrics,
+    certified_history_point: bool,
+    certified_events: bool,
+    certified_groups: bool,
+    retries: u32,
+    retry_backoff: Duration,
+    circuit: CircuitBreaker,
+    shutdown: CancellationToken,
+}
+
+/// Error of the call made while the circuit is open, the IC is not called at all.
+#[derive(Debug)]
+pub struct CircuitOpen {
+    pub retry_in: Duration,
+}
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ICP circuit is open, retrying in {}ms",
+            self.retry_in.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+impl ICPClient {
+    /// The `shutdown` token interrupts the backoff between the retries.
+    pub async fn new(
+        cfg: Config,
+        metrics: Metrics,
+        shutdown: CancellationToken,
+    ) -> eyre::Result<Self> {
+        let identity = identity_from_cfg(&cfg)?;
+
+        tracing::info!(
+            principal = identity.sender().map(|p| p.to_text()).unwrap_or_default(),
+            "Using ICP identity"
+        );
+
+        let agent = ic_agent::Agent::builder()
+            .with_url(cfg.ic_url.clone())
+            .with_boxed_identity(identity)
+            .build()
+            .wrap_err("Failed to create IC agent")?;
+
+        // The local replica has its own root key, never fetch it on the mainnet
+        if cfg.fetch_root_key {
+            tracing::warn!(
+                ic_url = cfg.ic_url,
+                "Fetching root key of the local replica"
+            );
+            agent
+                .fetch_root_key()
+                .await
+                .wrap_err("Failed to fetch root key")?;
+        }
+
+        Ok(Self {
+            agent,
+            proxy_id: cfg.proxy_id,
+            history_id: cfg.history_id,
+            limit: cfg.limit,
+            metrics,
+            certified_history_point: cfg.certified_history_point,
+            certified_events: cfg.certified_events,
+            certified_groups: cfg.certified_groups,
+            retries: cfg.icp_retries,
+            retry_backoff: Duration::from_millis(cfg.icp_retry_backoff),
+            circuit: CircuitBreaker::new(
+                cfg.icp_circuit_threshold,
+                Duration::from_millis(cfg.icp_circuit_timeout),
+            ),
+            shutdown,
+        })
+    }
+
+    async fn call_groups(&self, method: &str, args: Vec<u8>) -> eyre::Result<Vec<u8>> {
+        self.call(&self.proxy_id, method, args, self.certified_groups)
+            .await
+    }
+
+    /// Retries the call on the transient errors, fails fast while the circuit is open.
+    async fn call(
+        &self,
+        canister_id: &Principal,
+        method: &str,
+        args: Vec<u8>,
+        certified: bool,
+    ) -> eyre::Result<Vec<u8>> {
+        if let Some(retry_in) = self.circuit.try_acquire() {
+            return Err(CircuitOpen { retry_in }.into());
+        }
+
+        let mut attempt = 0;
+
+        let res = loop {
+            attempt += 1;
+
+            match self.call_once(canister_id, method, &args, certified).await {
+                Err(e) if is_transient(&e) && attempt <= self.retries => {
+                    let delay = backoff(self.retry_backoff, attempt, self.retry_backoff * 16);
+
+                    tracing::warn!(
+                        method,
+                        attempt,
+                        err = e.to_string(),
+                        "ICP call has failed, retrying in {}ms",
+                        delay.as_millis()
+                    );
+
+                    self.metrics.icp_retry(method);
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        // The last error is returned, the caller is stopping anyway
+                        _ = self.shutdown.cancelled() => break Err(e),
+                    }
+                }
+                res => break res,
+            }
+        };
+
+        match &res {
+            Ok(_) => self.circuit.success(),
+            Err(e) if is_transient(e) => self.circuit.failure(),
+            // Not an availability error, e.g. the canister has rejected the call, the counters stay
+            Err(_) => self.circuit.release(),
+        }
+
+        self.metrics.set_icp_circuit_state(self.circuit.state());
+
+        res.wrap_err_with(|| format!("Failed to perform \"{}\" request", method))
+    }
+
+    /// Performs the certified update call if `certified` is set, the query call otherwise.
+    async fn call_once(
+        &self,
+        canister_id: &Principal,
+        method: &str,
+        args: &[u8],
+        certified: bool,
+    ) -> Result<Vec<u8>, AgentError> {
+        let started = Instant::now();
+
+        let response = match certified {
+            // Polls the request status until the call is executed by the subnet
+            true => {
+                self.agent
+                    .update(canister_id, method)
+                    .with_arg(args.to_vec())
+                    .call_and_wait()
+                    .await
+            }
+            false => {
+                self.agent
+                    .query(canister_id, method)
+                    .with_arg(args.to_vec())
+                    .call()
+                    .await
+            }
+        };
+
+        self.metrics
+            .observe_icp_query(method, started.elapsed(), response.is_ok());
+
+        response
+    }
+}
+
+#[async_trait]
+impl IcpApi for ICPClient {
+    async fn get_history_point(&self) -> eyre::Result<u64> {
+        let response = self
+            .call(
+                &self.proxy_id,
+                "get_history_point",
+                Encode!()?,
+                self.certified_history_point,
+            )
+            .await?;
+        CanisterResult::try_from(response.as_slice())?.into_result()
+    }
+
+    async fn get_events(&self, from: u64) -> eyre::Result<Vec<HistoryEventEntry>> {
+        let args = Encode!(&from, &self.limit)?;
+        let response = self
+            .call(&self.history_id, "get_events", args, self.certified_events)
+            .await?;
+        CanisterResult::try_from(response.as_slice())?.into_result()
+    }
+
+    async fn get_group(&self, group_id: u64) -> eyre::Result<Group> {
+        let response = self.call_groups("get_group", Encode!(&group_id)?).await?;
+        let group: GroupResponse = CanisterResult::try_from(response.as_slice())?.into_result()?;
+        Ok(group.into())
+    }
+
+    async fn get_groups(&self, page: u64) -> eyre::Result<GroupsPage> {
+        let filters: Vec<GroupFilter> = vec![];
+        let args = Encode!(&self.limit, &page, &filters, &GroupSort::default())?;
+        let response = self.call_groups("get_groups", args).await?;
+        let groups: PagedResponse<GroupResponse> =
+            CanisterResult::try_from(response.as_slice())?.into_result()?;
+        Ok(groups.into())
+    }
+
+    async fn get_group_members(&self, group_id: u64) -> eyre::Result<Vec<GroupMember>> {
+        let response = self
+            .call_groups("get_group_members", Encode!(&group_id)?)
+            .await?;
+        let members: Vec<JoinedMemberResponse> =
+            CanisterResult::try_from(response.as_slice())?.into_result()?;
+        Ok(members.into_iter().map(GroupMember::from).collect())
+    }
+
+    fn circuit_state(&self) -> CircuitState {
+        self.circuit.state()
+    }
+
+    fn circuit_retry_in(&self) -> Option<Duration> {
+        self.circuit.retry_in()
+    }
+}
+
+/// Returns true if the error of the ICP call is worth retrying: the IC or the network is
+/// temporarily unavailable or the call was made while the circuit is open.
+pub fn is_unavailable(e: &eyre::Report) -> bool {
+    e.chain()
+        .any(|e| e.is::<CircuitOpen>() || e.downcast_ref::<AgentError>().is_some_and(is_transient))
+}
+
+fn is_transient(e: &AgentError) -> bool {
+    match e {
+        AgentError::TransportError(_) | AgentError::TimeoutWaitingForResponse() => true,
+        // Overloaded boundary node or replica
+        AgentError::HttpError(payload) => payload.status == 429 || payload.status >= 500,
+        AgentError::CertifiedReject(reject) | AgentError::UncertifiedReject(reject) => {
+            reject.reject_code == RejectCode::SysTransient
+        }
+        // The certificate is rejected during the subnet key rotation or a replica lag
+        AgentError::CertificateVerificationFailed()
+        | AgentError::CertificateNotAuthorized()
+        | AgentError::CertificateOutdated(_) => true,
+        _ => false,
+    }
+}
+
+/// Loads the PEM identity from `identity_pem` or `identity_path`, falls back to the anonymous one.
+fn identity_from_cfg(cfg: &Config) -> eyre::Result<Box<dyn Identity>> {
+    let pem = match (&cfg.identity_pem, &cfg.identity_path) {
+        (Some(pem), _) => pem.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read identity from \"{path}\""))?,
+        (None, None) => return Ok(Box::new(AnonymousIdentity)),
+    };
+
+    // `dfx identity new` writes secp256k1 keys as SEC1 and Ed25519 keys as PKCS#8
+    if pem.contains("EC PRIVATE KEY") {
+        let identity = Secp256k1Identity::from_pem(pem.as_bytes())
+            .wrap_err("Failed to parse secp256k1 identity")?;
+        return Ok(Box::new(identity));
+    }
+
+    let identity =
+        BasicIdentity::from_pem(pem.as_bytes()).wrap_err("Failed to parse Ed25519 identity")?;
+    Ok(Box::new(identity))
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use candid::{CandidType, Encode};
+use eyre::eyre;
+use proxy_types::models::history_event::{HistoryEvent, HistoryEventEntry, HistoryEventKind};
+
+use super::{CircuitOpen, IcpApi};
+use crate::types::{CanisterError, Group, GroupMember, GroupsPage};
+
+/// Scripted in-memory proxy and history canisters for the offline tests.
+pub struct MockIcp {
+    inner: Mutex<Inner>,
+    limit: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    events: Vec<HistoryEventEntry>,
+    groups: BTreeMap<u64, Group>,
+    members: HashMap<u64, Vec<GroupMember>>,
+    unavailable: bool,
+}
+
+impl MockIcp {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            inner: Mutex::default(),
+            limit,
+        }
+    }
+
+    /// Writes the event at the next history point, as the history canister does.
+    pub fn push_event(&self, kind: HistoryEventKind, payload: &impl CandidType) -> u64 {
+        let mut inner = self.lock();
+        let history_point = inner.events.len() as u64 + 1;
+
+        inner.events.push((history_point, event(kind, payload)));
+        history_point
+    }
+
+    pub fn set_group(&self, group_id: u64, group: Group) {
+        self.lock().groups.insert(
+            group_id,
+            Group {
+                id: group_id,
+                ..group
+            },
+        );
+    }
+
+    pub fn set_group_members(&self, group_id: u64, members: Vec<GroupMember>) {
+        self.lock().members.insert(group_id, members);
+    }
+
+    /// Fails all of the calls as if the IC is down.
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.lock().unavailable = unavailable;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("Mock ICP lock is poisoned")
+    }
+
+    fn check_available(&self) -> eyre::Result<MutexGuard<'_, Inner>> {
+        let inner = self.lock();
+
+        if inner.unavailable {
+            return Err(eyre!(CircuitOpen {
+                retry_in: Default::default()
+            }));
+        }
+
+        Ok(inner)
+    }
+}
+
+/// Returns the event as it is written by the history canister.
+pub fn event(kind: HistoryEventKind, payload: &impl CandidType) -> HistoryEvent {
+    HistoryEvent {
+        kind: kind.to_string(),
+        data: Encode!(payload).expect("Failed to encode mock event payload"),
+    }
+}
+
+#[async_trait]
+impl IcpApi for MockIcp {
+    async fn get_history_point(&self) -> eyre::Result<u64> {
+        let inner = self.check_available()?;
+        Ok(inner.events.len() as u64 + 1)
+    }
+
+    async fn get_events(&self, from: u64) -> eyre::Result<Vec<HistoryEventEntry>> {
+        let inner = self.check_available()?;
+
+        Ok(inner
+            .events
+            .iter()
+            .filter(|(history_point, _)| *history_point >= from)
+            .take(self.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_group(&self, group_id: u64) -> eyre::Result<Group> {
+        let inner = self.check_available()?;
+
+        inner
+            .groups
+            .get(&group_id)
+            .cloned()
+            .ok_or_else(|| CanisterError("Group is not found".to_owned()).into())
+    }
+
+    async fn get_groups(&self, page: u64) -> eyre::Result<GroupsPage> {
+        let inner = self.check_available()?;
+        let limit = self.limit.max(1) as usize;
+
+        Ok(GroupsPage {
+            groups: inner
+                .groups
+                .values()
+                .skip(page.saturating_sub(1) as usize * limit)
+                .take(limit)
+                .cloned()
+                .collect(),
+            number_of_pages: inner.groups.len().div_ceil(limit) as u64,
+        })
+    }
+
+    async fn get_group_members(&self, group_id: u64) -> eyre::Result<Vec<GroupMember>> {
+        let inner = self.check_available()?;
+        Ok(inner.members.get(&group_id).cloned().unwrap_or_default())
+    }
+}
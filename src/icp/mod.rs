@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use proxy_types::models::history_event::HistoryEventEntry;
+
+use crate::{
+    types::{Group, GroupMember, GroupsPage},
+    utils::CircuitState,
+};
+
+mod client;
+#[cfg(test)]
+pub mod mock;
+
+pub use client::{is_unavailable, CircuitOpen, ICPClient};
+
+/// Calls of the proxy and history canisters, which the relayer depends on.
+#[async_trait]
+pub trait IcpApi: Send + Sync {
+    /// Returns the history point the next event will be written at.
+    async fn get_history_point(&self) -> eyre::Result<u64>;
+
+    /// Returns up to `limit` events starting from the `from` history point.
+    async fn get_events(&self, from: u64) -> eyre::Result<Vec<HistoryEventEntry>>;
+
+    async fn get_group(&self, group_id: u64) -> eyre::Result<Group>;
+
+    /// Returns the page of up to `limit` groups, the pages start from 1.
+    async fn get_groups(&self, page: u64) -> eyre::Result<GroupsPage>;
+
+    async fn get_group_members(&self, group_id: u64) -> eyre::Result<Vec<GroupMember>>;
+
+    fn circuit_state(&self) -> CircuitState {
+        CircuitState::Closed
+    }
+
+    /// Returns the time left until the IC is called again, `None` if the circuit is closed.
+    fn circuit_retry_in(&self) -> Option<Duration> {
+        None
+    }
+}
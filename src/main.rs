@@ -1,61 +1,190 @@
+use std::{future::Future, time::Duration};
+
 use config::Config;
+use consumer::QueueKey;
 use context::Context;
 use eyre::Context as _;
-use matrix_sdk::config::SyncSettings;
 use proxy_types::models::history_event::HistoryEventKind;
-use tokio::task::JoinHandle;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::{JoinHandle, JoinSet},
+};
 use utils::with_spans;
 
+mod appservice;
 mod config;
 mod consts;
 mod consumer;
 mod context;
-mod data;
+mod health;
+mod http;
 mod icp;
 mod matrix;
+mod metrics;
 mod producer;
+mod reconciler;
+mod store;
+mod supervisor;
+#[cfg(test)]
+mod testing;
 mod types;
 mod utils;
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    let ctx = Context::new(Config::from_env()?).await?;
+    let cfg = Config::from_env()?;
+
+    let _tracing = utils::init_tracing(cfg.log_filter.clone(), cfg.otel_endpoint.clone())?;
 
-    utils::init_tracing(ctx.config().log_filter.clone());
+    if cfg.appservice_mode {
+        appservice::write_registration(&cfg)?;
+    }
+
+    let ctx = Context::new(cfg).await?;
     tracing::info!("Starting service with config: {}", ctx.config());
 
-    let producer_task = tokio::spawn(with_spans("producer", producer::run(ctx.clone())));
+    // Served during the login already, the readiness probe fails until it is completed
+    let http_task = tokio::spawn(with_spans("http", http::run(ctx.clone())));
+
+    let appservice_task = tokio::spawn(with_spans("appservice", appservice::run(ctx.clone())));
+
+    matrix::authorize(ctx.clone())
+        .await
+        .wrap_err("Failed to authorize matrix client")?;
+
+    let producer_task = supervisor::spawn(ctx.clone(), "producer", {
+        let ctx = ctx.clone();
+        move || producer::run(ctx.clone())
+    });
 
     let group_role_consumer_task = consumer::spawn(
         ctx.clone(),
-        HistoryEventKind::GroupRoleChanged,
+        QueueKey::from(HistoryEventKind::GroupRoleChanged),
+        consumer::group_role_partition,
         consumer::handle_group_role,
     );
 
-    let matrix_sync_task: JoinHandle<eyre::Result<()>> =
-        tokio::spawn(with_spans("matrix_sync", async move {
-            ctx.matrix()
-                .sync(SyncSettings::default())
-                .await
-                .wrap_err("Failed to sync with matrix server")
-        }));
+    let group_created_consumer_task = consumer::spawn(
+        ctx.clone(),
+        QueueKey::from(HistoryEventKind::GroupCreated),
+        consumer::group_created_partition,
+        consumer::handle_group_created,
+    );
+
+    let group_updated_consumer_task = consumer::spawn(
+        ctx.clone(),
+        QueueKey::from(HistoryEventKind::GroupUpdated),
+        consumer::group_updated_partition,
+        consumer::handle_group_updated,
+    );
 
-    tokio::select! {
-        res = producer_task => {
-            tracing::error!("Producer has quit unexpectedly");
-            res??
-        }
+    let group_membership_consumer_task = consumer::spawn(
+        ctx.clone(),
+        QueueKey::group_membership(),
+        consumer::group_membership_partition,
+        consumer::handle_group_membership,
+    );
 
-        res = group_role_consumer_task => {
-            tracing::error!("Group role change consumer has quit unexpectedly");
-            res??
+    let group_ban_consumer_task = consumer::spawn(
+        ctx.clone(),
+        QueueKey::group_ban(),
+        consumer::group_ban_partition,
+        consumer::handle_group_ban,
+    );
+
+    let reconciler_task = supervisor::spawn(ctx.clone(), "reconciler", {
+        let ctx = ctx.clone();
+        move || reconciler::run(ctx.clone())
+    });
+
+    let matrix_sync_task = supervisor::spawn(ctx.clone(), "matrix_sync", {
+        let ctx = ctx.clone();
+        move || matrix::sync(ctx.clone())
+    });
+
+    // Any task that returns before the shutdown stops the whole service, so the disabled ones
+    // (e.g. the reconciler or the appservice) wait for the shutdown instead of returning
+    let mut tasks = JoinSet::new();
+    tasks.spawn(named("Producer", producer_task));
+    tasks.spawn(named(
+        "Group role change consumer",
+        group_role_consumer_task,
+    ));
+    tasks.spawn(named("Group created consumer", group_created_consumer_task));
+    tasks.spawn(named("Group updated consumer", group_updated_consumer_task));
+    tasks.spawn(named(
+        "Group membership consumer",
+        group_membership_consumer_task,
+    ));
+    tasks.spawn(named("Group ban consumer", group_ban_consumer_task));
+    tasks.spawn(named("Reconciler", reconciler_task));
+    tasks.spawn(named("HTTP server", http_task));
+    tasks.spawn(named("Appservice", appservice_task));
+    tasks.spawn(named("Matrix sync task", matrix_sync_task));
+
+    let res = tokio::select! {
+        res = shutdown_signal() => {
+            tracing::info!("Received shutdown signal, draining in-flight events...");
+            res
         }
 
-        res = matrix_sync_task => {
-            tracing::error!("Matrix sync task has quit unexpectedly");
-            res??
+        Some(joined) = tasks.join_next() => {
+            let (name, res) = joined.wrap_err("Task has panicked")?;
+            tracing::error!("{name} has quit unexpectedly");
+            res
         }
+    };
+
+    ctx.shutdown().cancel();
+    drain(tasks, Duration::from_millis(ctx.config().shutdown_timeout)).await;
+
+    res
+}
+
+fn named(
+    name: &'static str,
+    task: JoinHandle<eyre::Result<()>>,
+) -> impl Future<Output = (&'static str, eyre::Result<()>)> {
+    async move {
+        let res = match task.await {
+            Ok(res) => res,
+            Err(e) => Err(e).wrap_err("Task has panicked"),
+        };
+        (name, res)
     }
+}
 
-    Ok(())
+async fn shutdown_signal() -> eyre::Result<()> {
+    let mut terminate =
+        signal(SignalKind::terminate()).wrap_err("Failed to listen for SIGTERM signal")?;
+
+    tokio::select! {
+        _ = terminate.recv() => Ok(()),
+        res = tokio::signal::ctrl_c() => res.wrap_err("Failed to listen for SIGINT signal"),
+    }
+}
+
+/// Waits for the tasks to finish their current work, the tasks left after the deadline are aborted.
+async fn drain(mut tasks: JoinSet<(&'static str, eyre::Result<()>)>, deadline: Duration) {
+    let drained = tokio::time::timeout(deadline, async {
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((name, Err(e))) => {
+                    tracing::error!(err = format!("{e:#}"), "{name} has failed during shutdown")
+                }
+                Ok((name, Ok(()))) => tracing::debug!("{name} has stopped"),
+                Err(e) => tracing::error!(err = e.to_string(), "Task has panicked during shutdown"),
+            }
+        }
+    })
+    .await;
+
+    match drained {
+        Ok(()) => tracing::info!("Shutdown completed"),
+        Err(_) => tracing::warn!(
+            "Shutdown deadline of {}ms is exceeded, aborting {} task(s)",
+            deadline.as_millis(),
+            tasks.len()
+        ),
+    }
 }
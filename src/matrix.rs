@@ -2,38 +2,176 @@ use std::{sync::Arc, time::Duration};
 
 use eyre::{bail, Context as _, OptionExt};
 use matrix_sdk::{
+    config::SyncSettings,
+    matrix_auth::MatrixSession,
     ruma::{
-        api::client::space::{get_hierarchy, SpaceHierarchyRoomsChunk},
-        events::{room::member::StrippedRoomMemberEvent, StateEventType},
-        Int, OwnedRoomId, UInt, UserId,
+        api::client::{
+            account::whoami,
+            error::ErrorKind,
+            room::create_room::v3::{CreationContent, Request as CreateRoomRequest},
+            space::{get_hierarchy, SpaceHierarchyRoomsChunk},
+        },
+        events::{
+            room::member::{MembershipState, StrippedRoomMemberEvent},
+            space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
+            StateEventType,
+        },
+        room::RoomType,
+        serde::Raw,
+        Int, MxcUri, OwnedMxcUri, OwnedRoomId, OwnedUserId, RoomId, UInt, UserId,
     },
-    Client, HttpError, Room,
+    Client, HttpError, LoopCtrl, Room, RoomMemberships,
 };
 
 use crate::{
-    config::Config, consts::MATRIX_USER_ID, context::Context, types::MatrixUserID,
-    utils::with_spans,
+    appservice, config::Config, consts::MATRIX_USER_ID, context::Context, store::EventStore,
+    types::MatrixUserID, utils::with_spans,
 };
 
 static MAX_JOIN_RETRY_DELAY: u64 = 3600;
 
+/// Creates the matrix client, it has to be authorized with [`authorize`] before use.
 pub async fn client_from_cfg(cfg: &Config) -> eyre::Result<Client> {
-    let client = Client::builder()
+    Client::builder()
         .homeserver_url(cfg.matrix_url.clone())
+        .sqlite_store(&cfg.matrix_store_path, None)
         .build()
         .await
-        .wrap_err("Failed to create matrix client")?;
+        .wrap_err("Failed to create matrix client")
+}
+
+/// Restores the stored session or logs in with the password, the relayer is not ready until then.
+pub async fn authorize(ctx: Arc<Context>) -> eyre::Result<()> {
+    restore_or_login(&ctx.matrix(), &ctx.config(), ctx.store()).await?;
+    ctx.health().set_logged_in();
+    Ok(())
+}
+
+async fn restore_or_login(
+    client: &Client,
+    cfg: &Config,
+    store: Arc<dyn EventStore>,
+) -> eyre::Result<()> {
+    if cfg.appservice_mode {
+        let session = appservice::session(cfg, appservice::relayer_id(cfg)?);
+
+        client
+            .restore_session(session)
+            .await
+            .wrap_err("Failed to restore appservice session")?;
+
+        // Invites are pushed to the appservice endpoint, see `appservice::run`
+        return Ok(());
+    }
+
+    let session = store
+        .get_matrix_session(&cfg.consumer_name)
+        .await
+        .wrap_err("Failed to get matrix session from the store")?
+        .map(|session| serde_json::from_str::<MatrixSession>(&session))
+        .transpose()
+        .wrap_err("Failed to deserialize matrix session")?;
+
+    match session {
+        Some(session) => {
+            let device_id = session.meta.device_id.clone();
+
+            client
+                .restore_session(session)
+                .await
+                .wrap_err("Failed to restore matrix session")?;
+
+            match client.send(whoami::v3::Request::new(), None).await {
+                Ok(_) => {
+                    tracing::info!(device_id = device_id.to_string(), "Restored matrix session")
+                }
+                Err(e) if is_unknown_token(&e) => {
+                    tracing::warn!(
+                        device_id = device_id.to_string(),
+                        "Matrix session token is rejected, logging in with the password"
+                    );
+                    login(client, cfg, store, Some(device_id.to_string())).await?;
+                }
+                Err(e) => return Err(e).wrap_err("Failed to check restored matrix session"),
+            }
+        }
+        None => login(client, cfg, store, None).await?,
+    }
 
-    client
-        .matrix_auth()
+    client.add_event_handler(on_stripped_state_member);
+
+    Ok(())
+}
+
+/// Logs in with the password, reusing the device if its id is given, and saves the new session.
+async fn login(
+    client: &Client,
+    cfg: &Config,
+    store: Arc<dyn EventStore>,
+    device_id: Option<String>,
+) -> eyre::Result<()> {
+    let auth = client.matrix_auth();
+    let mut login = auth
         .login_username(MATRIX_USER_ID, &cfg.password)
-        .initial_device_display_name(MATRIX_USER_ID)
+        .initial_device_display_name(MATRIX_USER_ID);
+
+    if let Some(device_id) = device_id.as_deref() {
+        login = login.device_id(device_id);
+    }
+
+    login
         .await
         .wrap_err("Failed to authorize with the matrix client")?;
 
-    client.add_event_handler(on_stripped_state_member);
+    let session = auth
+        .session()
+        .ok_or_eyre("Failed to get matrix session after the login")?;
 
-    Ok(client)
+    let session = serde_json::to_string(&session).wrap_err("Failed to serialize matrix session")?;
+
+    store
+        .set_matrix_session(&cfg.consumer_name, session)
+        .await
+        .wrap_err("Failed to save matrix session to the store")?;
+
+    tracing::info!("Logged in to the matrix server");
+    Ok(())
+}
+
+fn is_unknown_token(err: &HttpError) -> bool {
+    matches!(
+        err.client_api_error_kind(),
+        Some(ErrorKind::UnknownToken { .. })
+    )
+}
+
+/// Syncs with the matrix server, the sync token and the rooms state are kept in the sqlite store
+/// of the matrix client, so the sync continues from the last token after restart.
+pub async fn sync(ctx: Arc<Context>) -> eyre::Result<()> {
+    let health_ctx = ctx.clone();
+
+    let client = ctx.matrix();
+    let sync = client.sync_with_callback(SyncSettings::default(), move |_| {
+        let ctx = health_ctx.clone();
+        async move {
+            ctx.health().touch_sync();
+
+            match ctx.is_shutting_down() {
+                true => LoopCtrl::Break,
+                false => LoopCtrl::Continue,
+            }
+        }
+    });
+
+    // The long poll isn't waited for, the sync token is saved after each response anyway
+    let res = tokio::select! {
+        res = sync => res,
+        _ = ctx.shutdown().cancelled() => Ok(()),
+    };
+
+    tracing::info!("Matrix sync is stopped");
+
+    track(&ctx, "sync", res).wrap_err("Failed to sync with matrix server")
 }
 
 async fn on_stripped_state_member(
@@ -79,6 +217,8 @@ async fn on_stripped_state_member(
     }));
 }
 
+/// Returns the space and its direct child rooms. Fails if the hierarchy can't be fetched, so the
+/// event is retried instead of being applied to the space only.
 pub async fn get_space_rooms(
     ctx: Arc<Context>,
     space_id: OwnedRoomId,
@@ -86,29 +226,50 @@ pub async fn get_space_rooms(
     let mut req = get_hierarchy::v1::Request::new(space_id.clone());
     req.max_depth = UInt::new(1); // Only get the direct children of the space
 
-    let resp = send_hierarchy_request(ctx.clone(), req.clone())
-        .await
-        .map_err(|e| {
-            tracing::warn!(
-                err = e.to_string(),
-                space_id = space_id.to_string(),
-                "Failed to get space hierarchy"
-            );
-        });
-
-    let Ok(mut resp) = resp else {
-        return Ok(vec![]);
-    };
+    let mut rooms = vec![];
 
-    let mut rooms = room_ids_from_chunks(resp.rooms);
+    loop {
+        let resp = send_hierarchy_request(ctx.clone(), req.clone())
+            .await
+            .wrap_err_with(|| format!("Failed to get hierarchy of the space \"{space_id}\""))?;
 
-    while let Some(next_batch) = resp.next_batch.clone() {
-        req.from = Some(next_batch);
-        resp = send_hierarchy_request(ctx.clone(), req.clone()).await?;
         rooms.extend(room_ids_from_chunks(resp.rooms));
+
+        match resp.next_batch {
+            Some(next_batch) => req.from = Some(next_batch),
+            None => return Ok(rooms),
+        }
     }
+}
+
+/// Returns the names of the direct child rooms of the space, fails if the hierarchy can't be
+/// fetched, so the caller doesn't take the rooms for missing.
+pub async fn get_space_room_names(
+    ctx: Arc<Context>,
+    space_id: OwnedRoomId,
+) -> eyre::Result<Vec<String>> {
+    let mut req = get_hierarchy::v1::Request::new(space_id.clone());
+    req.max_depth = UInt::new(1); // Only get the direct children of the space
+
+    let mut names = vec![];
+
+    loop {
+        let resp = send_hierarchy_request(ctx.clone(), req.clone())
+            .await
+            .wrap_err_with(|| format!("Failed to get hierarchy of the space \"{space_id}\""))?;
 
-    Ok(rooms)
+        names.extend(
+            resp.rooms
+                .into_iter()
+                .filter(|chunk| chunk.room_id != space_id)
+                .filter_map(|chunk| chunk.name),
+        );
+
+        match resp.next_batch {
+            Some(next_batch) => req.from = Some(next_batch),
+            None => return Ok(names),
+        }
+    }
 }
 
 pub async fn set_member_power_level(
@@ -118,42 +279,482 @@ pub async fn set_member_power_level(
     power_level: u64,
 ) -> eyre::Result<Option<OwnedRoomId>> {
     let matrix = ctx.matrix();
-    let room = matrix.get_room(&room_id);
 
-    if room.is_none() {
-        tracing::info!(
+    let Some(room) = find_room(&matrix, &room_id, "power level update") else {
+        return Ok(None);
+    };
+
+    ensure_can_send_state(&matrix, &room, StateEventType::RoomPowerLevels).await?;
+
+    let user_id = user_id.to_user_id()?;
+
+    let power_level = Int::try_from(power_level)
+        .wrap_err_with(|| format!("Failed to convert power level: {power_level}"))?;
+
+    let res = room
+        .update_power_levels(vec![(&user_id, power_level)])
+        .await;
+    track(&ctx, "power_levels", res)?;
+
+    Ok(Some(room_id))
+}
+
+/// Returns the default power level of the room and its joined members with their power levels,
+/// `None` if the room is not found.
+pub async fn get_member_power_levels(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+) -> eyre::Result<Option<(i64, Vec<(OwnedUserId, i64)>)>> {
+    let matrix = ctx.matrix();
+
+    let Some(room) = find_room(&matrix, &room_id, "reading power levels") else {
+        return Ok(None);
+    };
+
+    let power_levels = room
+        .power_levels()
+        .await
+        .wrap_err_with(|| format!("Failed to get power levels of the room \"{room_id}\""))?;
+
+    let members = room
+        .members_no_sync(RoomMemberships::JOIN)
+        .await
+        .wrap_err_with(|| format!("Failed to get members of the room \"{room_id}\""))?;
+
+    let levels = members
+        .iter()
+        .map(|member| {
+            let user_id = member.user_id().to_owned();
+            let level = power_levels.for_user(&user_id).into();
+            (user_id, level)
+        })
+        .collect();
+
+    Ok(Some((power_levels.users_default.into(), levels)))
+}
+
+/// Invites the member to the room, does nothing if the member is already joined or invited.
+pub async fn invite_member(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+    user_id: MatrixUserID,
+) -> eyre::Result<Option<OwnedRoomId>> {
+    let matrix = ctx.matrix();
+
+    let Some(room) = find_room(&matrix, &room_id, "invite") else {
+        return Ok(None);
+    };
+
+    let relayer_id = matrix
+        .user_id()
+        .ok_or_eyre("Failed to get relayer id during checking room permissions")?;
+
+    let can_invite = room
+        .can_user_invite(relayer_id)
+        .await
+        .wrap_err("Failed to get can relayer invite members")?;
+
+    if !can_invite {
+        bail!("User does not have permission to invite members")
+    }
+
+    let user_id = user_id.to_user_id()?;
+
+    if let MembershipState::Join | MembershipState::Invite = membership(&room, &user_id).await? {
+        tracing::debug!(
+            room_id = room_id.to_string(),
+            user_id = user_id.to_string(),
+            "Member is already in the room, skipping invite"
+        );
+        return Ok(None);
+    }
+
+    track(&ctx, "invite", room.invite_user_by_id(&user_id).await)
+        .wrap_err_with(|| format!("Failed to invite \"{user_id}\" to the room \"{room_id}\""))?;
+
+    Ok(Some(room_id))
+}
+
+/// Joins the invited member to the room on behalf of the member, only in the appservice mode.
+pub async fn accept_invite(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+    user_id: MatrixUserID,
+) -> eyre::Result<()> {
+    let user_id = user_id.to_user_id()?;
+
+    let client = appservice::masquerade(&ctx, &user_id).await?;
+
+    track(&ctx, "join", client.join_room_by_id(&room_id).await)
+        .wrap_err_with(|| format!("Failed to join \"{user_id}\" to the room \"{room_id}\""))?;
+
+    Ok(())
+}
+
+/// Kicks the member from the room, does nothing if the member is not joined or invited.
+pub async fn kick_member(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+    user_id: MatrixUserID,
+    reason: Option<&str>,
+) -> eyre::Result<Option<OwnedRoomId>> {
+    let matrix = ctx.matrix();
+
+    let Some(room) = find_room(&matrix, &room_id, "kick") else {
+        return Ok(None);
+    };
+
+    let relayer_id = matrix
+        .user_id()
+        .ok_or_eyre("Failed to get relayer id during checking room permissions")?;
+
+    let can_kick = room
+        .can_user_kick(relayer_id)
+        .await
+        .wrap_err("Failed to get can relayer kick members")?;
+
+    if !can_kick {
+        bail!("User does not have permission to kick members")
+    }
+
+    let user_id = user_id.to_user_id()?;
+
+    if let MembershipState::Leave | MembershipState::Ban = membership(&room, &user_id).await? {
+        tracing::debug!(
+            room_id = room_id.to_string(),
+            user_id = user_id.to_string(),
+            "Member is not in the room, skipping kick"
+        );
+        return Ok(None);
+    }
+
+    track(&ctx, "kick", room.kick_user(&user_id, reason).await)
+        .wrap_err_with(|| format!("Failed to kick \"{user_id}\" from the room \"{room_id}\""))?;
+
+    Ok(Some(room_id))
+}
+
+/// Bans the member in the room, does nothing if the member is already banned.
+pub async fn ban_member(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+    user_id: MatrixUserID,
+    reason: &str,
+) -> eyre::Result<Option<OwnedRoomId>> {
+    let matrix = ctx.matrix();
+
+    let Some(room) = find_room(&matrix, &room_id, "ban") else {
+        return Ok(None);
+    };
+
+    ensure_can_ban(&matrix, &room).await?;
+
+    let user_id = user_id.to_user_id()?;
+
+    if let MembershipState::Ban = membership(&room, &user_id).await? {
+        tracing::debug!(
             room_id = room_id.to_string(),
-            "Room not found, skipping power level update"
+            user_id = user_id.to_string(),
+            "Member is already banned, skipping ban"
         );
+        return Ok(None);
+    }
+
+    track(&ctx, "ban", room.ban_user(&user_id, Some(reason)).await)
+        .wrap_err_with(|| format!("Failed to ban \"{user_id}\" in the room \"{room_id}\""))?;
+
+    Ok(Some(room_id))
+}
+
+/// Unbans the member in the room, does nothing if the member is not banned.
+pub async fn unban_member(
+    ctx: Arc<Context>,
+    room_id: OwnedRoomId,
+    user_id: MatrixUserID,
+    reason: &str,
+) -> eyre::Result<Option<OwnedRoomId>> {
+    let matrix = ctx.matrix();
+
+    let Some(room) = find_room(&matrix, &room_id, "unban") else {
+        return Ok(None);
+    };
+
+    ensure_can_ban(&matrix, &room).await?;
 
+    let user_id = user_id.to_user_id()?;
+
+    if membership(&room, &user_id).await? != MembershipState::Ban {
+        tracing::debug!(
+            room_id = room_id.to_string(),
+            user_id = user_id.to_string(),
+            "Member is not banned, skipping unban"
+        );
         return Ok(None);
     }
 
-    let room = room.unwrap();
+    track(&ctx, "unban", room.unban_user(&user_id, Some(reason)).await)
+        .wrap_err_with(|| format!("Failed to unban \"{user_id}\" in the room \"{room_id}\""))?;
+
+    Ok(Some(room_id))
+}
+
+/// Creates a space, the relayer becomes its creator with the highest power level.
+pub async fn create_space(
+    ctx: Arc<Context>,
+    name: String,
+    topic: Option<String>,
+) -> eyre::Result<OwnedRoomId> {
+    let mut creation_content = CreationContent::new();
+    creation_content.room_type = Some(RoomType::Space);
+
+    let mut req = CreateRoomRequest::new();
+    req.creation_content =
+        Some(Raw::new(&creation_content).wrap_err("Failed to serialize space creation content")?);
+    req.name = Some(name.clone());
+    req.topic = topic;
+
+    let space = track(&ctx, "create_room", ctx.matrix().create_room(req).await)
+        .wrap_err_with(|| format!("Failed to create space \"{name}\""))?;
+
+    Ok(space.room_id().to_owned())
+}
+
+/// Creates a room and links it to the space as a child.
+pub async fn create_space_room(
+    ctx: Arc<Context>,
+    space_id: OwnedRoomId,
+    name: String,
+) -> eyre::Result<OwnedRoomId> {
+    let matrix = ctx.matrix();
+
+    let Some(space) = find_room(&matrix, &space_id, "room creation") else {
+        bail!("Space \"{space_id}\" not found")
+    };
+
+    let via = vec![matrix
+        .user_id()
+        .ok_or_eyre("Failed to get relayer id during creating space room")?
+        .server_name()
+        .to_owned()];
+
+    let mut req = CreateRoomRequest::new();
+    req.name = Some(name.clone());
+
+    let room = track(&ctx, "create_room", matrix.create_room(req).await).wrap_err_with(|| {
+        format!("Failed to create room \"{name}\" in the space \"{space_id}\"")
+    })?;
+
+    let room_id = room.room_id().to_owned();
+
+    let res = space
+        .send_state_event_for_key(&room_id, SpaceChildEventContent::new(via.clone()))
+        .await;
+
+    track(&ctx, "send_state", res).wrap_err_with(|| {
+        format!("Failed to add room \"{room_id}\" to the space \"{space_id}\"")
+    })?;
+
+    let res = room
+        .send_state_event_for_key(&space_id, SpaceParentEventContent::new(via))
+        .await;
+
+    track(&ctx, "send_state", res).wrap_err_with(|| {
+        format!("Failed to set space \"{space_id}\" as a parent of the room \"{room_id}\"")
+    })?;
+
+    Ok(room_id)
+}
+
+/// Profile of the space, `None` fields are left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct SpaceProfile {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub avatar_url: Option<OwnedMxcUri>,
+}
+
+/// Updates the name, topic and avatar of the space, only the changed state events are sent.
+/// Returns the names of the updated state events.
+pub async fn update_space_profile(
+    ctx: Arc<Context>,
+    space_id: OwnedRoomId,
+    profile: SpaceProfile,
+) -> eyre::Result<Vec<StateEventType>> {
+    let matrix = ctx.matrix();
+
+    let Some(space) = find_room(&matrix, &space_id, "profile update") else {
+        return Ok(vec![]);
+    };
+
+    let mut updated = vec![];
+
+    if let Some(name) = profile
+        .name
+        .filter(|name| space.name().as_ref() != Some(name))
+    {
+        ensure_can_send_state(&matrix, &space, StateEventType::RoomName).await?;
+        track(&ctx, "send_state", space.set_name(name).await)
+            .wrap_err_with(|| format!("Failed to set name of the space \"{space_id}\""))?;
+        updated.push(StateEventType::RoomName);
+    }
+
+    if let Some(topic) = profile
+        .topic
+        .filter(|topic| space.topic().as_ref() != Some(topic))
+    {
+        ensure_can_send_state(&matrix, &space, StateEventType::RoomTopic).await?;
+        track(&ctx, "send_state", space.set_room_topic(&topic).await)
+            .wrap_err_with(|| format!("Failed to set topic of the space \"{space_id}\""))?;
+        updated.push(StateEventType::RoomTopic);
+    }
+
+    if let Some(avatar_url) = profile
+        .avatar_url
+        .filter(|url| space.avatar_url().as_ref() != Some(url))
+    {
+        ensure_can_send_state(&matrix, &space, StateEventType::RoomAvatar).await?;
+        let res = space.set_avatar_url(&avatar_url, None).await;
+        track(&ctx, "send_state", res)
+            .wrap_err_with(|| format!("Failed to set avatar of the space \"{space_id}\""))?;
+        updated.push(StateEventType::RoomAvatar);
+    }
+
+    Ok(updated)
+}
+
+/// Downloads the image and uploads it to the media repository of the homeserver, the `mxc://`
+/// urls are returned as is. The image is uploaded once per url, the uploaded one is reused after.
+pub async fn upload_image(ctx: Arc<Context>, url: &str) -> eyre::Result<OwnedMxcUri> {
+    let mxc: &MxcUri = url.into();
+    if mxc.is_valid() {
+        return Ok(mxc.to_owned());
+    }
+
+    let uploaded = ctx
+        .store()
+        .get_media(url)
+        .await
+        .wrap_err("Failed to get uploaded image")?;
+
+    if let Some(uploaded) = uploaded {
+        tracing::debug!(url, mxc = uploaded, "Image is already uploaded");
+        return Ok(uploaded.into());
+    }
+
+    let max_size = ctx.config().max_image_size;
+
+    let mut resp = ctx
+        .http()
+        .get(url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .wrap_err_with(|| format!("Failed to download image \"{url}\""))?;
+
+    if resp.content_length().is_some_and(|len| len > max_size) {
+        bail!("Image \"{url}\" is larger than {max_size} bytes");
+    }
+
+    let content_type: mime::Mime = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    // The content length is optional, so the size is checked while reading as well
+    let mut data = vec![];
+
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .wrap_err_with(|| format!("Failed to read image \"{url}\""))?
+    {
+        if (data.len() + chunk.len()) as u64 > max_size {
+            bail!("Image \"{url}\" is larger than {max_size} bytes");
+        }
+
+        data.extend_from_slice(&chunk);
+    }
 
+    let uploaded = ctx.matrix().media().upload(&content_type, data).await;
+
+    let uploaded = track(&ctx, "upload", uploaded)
+        .wrap_err_with(|| format!("Failed to upload image \"{url}\" to the media repository"))?;
+
+    ctx.store()
+        .set_media(url, uploaded.content_uri.to_string())
+        .await
+        .wrap_err("Failed to save uploaded image")?;
+
+    Ok(uploaded.content_uri)
+}
+
+fn find_room(matrix: &Client, room_id: &RoomId, action: &str) -> Option<Room> {
+    let room = matrix.get_room(room_id);
+
+    if room.is_none() {
+        tracing::info!(
+            room_id = room_id.to_string(),
+            "Room not found, skipping {action}"
+        );
+    }
+
+    room
+}
+
+/// Bails if the relayer is not allowed to send the state events of the given type to the room.
+async fn ensure_can_send_state(
+    matrix: &Client,
+    room: &Room,
+    event_type: StateEventType,
+) -> eyre::Result<()> {
     let relayer_id = matrix
         .user_id()
         .ok_or_eyre("Failed to get relayer id during checking room permissions")?;
 
     let can_send = room
-        .can_user_send_state(relayer_id, StateEventType::RoomPowerLevels)
+        .can_user_send_state(relayer_id, event_type.clone())
         .await
         .wrap_err("Failed to get can relayer send state events")?;
 
     if !can_send {
-        bail!("User does not have permission to set power levels")
+        bail!("User does not have permission to send \"{event_type}\" state events")
     }
 
-    let user_id = UserId::parse(user_id.to_string())
-        .wrap_err_with(|| format!("Failed to parse user id: {user_id}"))?;
+    Ok(())
+}
 
-    let power_level = Int::try_from(power_level)
-        .wrap_err_with(|| format!("Failed to convert power level: {power_level}"))?;
+/// Bans are the `m.room.member` state events, which additionally require the ban power level.
+async fn ensure_can_ban(matrix: &Client, room: &Room) -> eyre::Result<()> {
+    ensure_can_send_state(matrix, room, StateEventType::RoomMember).await?;
+
+    let relayer_id = matrix
+        .user_id()
+        .ok_or_eyre("Failed to get relayer id during checking room permissions")?;
 
-    room.update_power_levels(vec![(&user_id, power_level)])
-        .await?;
+    let can_ban = room
+        .can_user_ban(relayer_id)
+        .await
+        .wrap_err("Failed to get can relayer ban members")?;
 
-    Ok(Some(room_id))
+    if !can_ban {
+        bail!("User does not have permission to ban members")
+    }
+
+    Ok(())
+}
+
+/// Returns the membership of the user in the room, `Leave` if the user has never been in it.
+async fn membership(room: &Room, user_id: &UserId) -> eyre::Result<MembershipState> {
+    let member = room
+        .get_member_no_sync(user_id)
+        .await
+        .wrap_err_with(|| format!("Failed to get room member \"{user_id}\""))?;
+
+    Ok(member
+        .map(|member| member.membership().clone())
+        .unwrap_or(MembershipState::Leave))
 }
 
 fn room_ids_from_chunks(chunks: Vec<SpaceHierarchyRoomsChunk>) -> Vec<OwnedRoomId> {
@@ -164,16 +765,172 @@ async fn send_hierarchy_request(
     ctx: Arc<Context>,
     req: get_hierarchy::v1::Request,
 ) -> Result<get_hierarchy::v1::Response, HttpError> {
-    ctx.matrix().send(req, None).await
+    track(&ctx, "hierarchy", ctx.matrix().send(req, None).await)
+}
+
+/// Counts the failed Matrix API request of the endpoint in the metrics.
+fn track<T, E>(ctx: &Context, endpoint: &str, res: Result<T, E>) -> Result<T, E> {
+    if res.is_err() {
+        ctx.metrics().matrix_error(endpoint);
+    }
+
+    res
 }
 
 #[cfg(test)]
 mod tests {
+    use candid::Principal;
+    use tempfile::TempDir;
+
     use super::*;
+    use crate::{
+        icp::mock::MockIcp,
+        testing::{self, Homeserver},
+    };
 
     #[tokio::test]
     async fn test_login() {
         let ctx = Context::new(Config::from_env().unwrap()).await.unwrap();
-        client_from_cfg(&ctx.config()).await.unwrap();
+        authorize(ctx).await.unwrap();
+    }
+
+    async fn synced_context(homeserver: &Homeserver, dir: &TempDir) -> Arc<Context> {
+        let icp = Arc::new(MockIcp::new(100));
+        testing::synced_context(homeserver, testing::config(dir), icp).await
+    }
+
+    async fn has_joined(homeserver: &Homeserver, room_id: &str) -> bool {
+        homeserver.joined_rooms().await == vec![room_id.to_owned()]
+    }
+
+    #[tokio::test]
+    async fn test_login_saves_session() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        homeserver.sync(&[], &[]).await;
+
+        let ctx = synced_context(&homeserver, &dir).await;
+
+        assert_eq!(
+            ctx.matrix().user_id().unwrap().to_string(),
+            homeserver.relayer_id()
+        );
+        let replica = &ctx.config().consumer_name;
+        assert!(ctx
+            .store()
+            .get_matrix_session(replica)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(ctx.health().is_logged_in());
+    }
+
+    #[tokio::test]
+    async fn test_get_space_rooms() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let space_id = homeserver.room_id("space");
+        let children = vec![homeserver.room_id("general"), homeserver.room_id("news")];
+
+        homeserver.sync(&[], &[]).await;
+        homeserver.hierarchy(&space_id, &children).await;
+        let ctx = synced_context(&homeserver, &dir).await;
+
+        let rooms = get_space_rooms(ctx, RoomId::parse(&space_id).unwrap())
+            .await
+            .unwrap();
+
+        let rooms: Vec<String> = rooms.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            rooms,
+            vec![space_id, children[0].clone(), children[1].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_space_rooms_fails_without_hierarchy() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let space_id = homeserver.room_id("space");
+
+        // The hierarchy endpoint is not mounted, so the homeserver responds with 404
+        homeserver.sync(&[], &[]).await;
+        let ctx = synced_context(&homeserver, &dir).await;
+
+        let res = get_space_rooms(ctx, RoomId::parse(&space_id).unwrap()).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_power_level_skips_unknown_room() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        homeserver.sync(&[], &[]).await;
+
+        let ctx = synced_context(&homeserver, &dir).await;
+        let member = MatrixUserID::new(
+            Principal::anonymous(),
+            "alice".to_owned(),
+            homeserver.server_name(),
+        );
+        let room_id = RoomId::parse(homeserver.room_id("unknown")).unwrap();
+
+        let applied = set_member_power_level(ctx, room_id, member, 50)
+            .await
+            .unwrap();
+
+        assert_eq!(applied, None);
+        assert!(homeserver
+            .state_events("m.room.power_levels")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_image_reuses_uploaded_image() {
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), Arc::new(MockIcp::new(100))).await;
+        let url = "https://example.com/image.png";
+
+        ctx.store()
+            .set_media(url, "mxc://example.com/image".to_owned())
+            .await
+            .unwrap();
+
+        let mxc = upload_image(ctx, url).await.unwrap();
+        assert_eq!(mxc.as_str(), "mxc://example.com/image");
+    }
+
+    #[tokio::test]
+    async fn test_upload_image_rejects_large_image() {
+        let server = wiremock::MockServer::start().await;
+
+        wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_bytes(vec![0; 1024]))
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.max_image_size = 512;
+
+        let ctx = testing::context(cfg, Arc::new(MockIcp::new(100))).await;
+        let url = format!("{}/image.png", server.uri());
+
+        let err = upload_image(ctx.clone(), &url).await.unwrap_err();
+        assert!(err.to_string().contains("is larger than 512 bytes"));
+        assert_eq!(ctx.store().get_media(&url).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_auto_join_invited_room() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let invited_id = homeserver.room_id("invited");
+
+        homeserver.sync(&[], &[invited_id.clone()]).await;
+        let _ctx = synced_context(&homeserver, &dir).await;
+
+        testing::eventually(|| has_joined(&homeserver, &invited_id)).await;
     }
 }
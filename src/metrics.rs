@@ -0,0 +1,220 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Context as _;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+
+use crate::utils::CircuitState;
+
+static MODES: [&str; 2] = ["catchup", "listening"];
+
+/// Prometheus metrics of the relayer, served on the `/metrics` endpoint.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    producer_lag: IntGauge,
+    producer_mode: IntGaugeVec,
+    queue_depth: IntGaugeVec,
+    dead_letter_depth: IntGaugeVec,
+    events_processed: IntCounterVec,
+    events_failed: IntCounterVec,
+    handler_duration: HistogramVec,
+    icp_duration: HistogramVec,
+    icp_errors: IntCounterVec,
+    icp_retries: IntCounterVec,
+    icp_circuit_state: IntGauge,
+    matrix_errors: IntCounterVec,
+    task_restarts: IntCounterVec,
+    task_failures: IntGaugeVec,
+    task_last_failure: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> eyre::Result<Self> {
+        let registry = Registry::new_custom(Some("relayer".to_owned()), None)
+            .wrap_err("Failed to create metrics registry")?;
+
+        Ok(Self {
+            producer_lag: register_int_gauge_with_registry!(
+                "producer_lag",
+                "Actual history point minus the stored one",
+                registry
+            )?,
+            producer_mode: register_int_gauge_vec_with_registry!(
+                "producer_mode",
+                "Current mode of the producer, 1 for the active one",
+                &["mode"],
+                registry
+            )?,
+            queue_depth: register_int_gauge_vec_with_registry!(
+                "queue_depth",
+                "Number of the events in the queue",
+                &["queue"],
+                registry
+            )?,
+            dead_letter_depth: register_int_gauge_vec_with_registry!(
+                "dead_letter_depth",
+                "Number of the events in the dead letter queue",
+                &["queue"],
+                registry
+            )?,
+            events_processed: register_int_counter_vec_with_registry!(
+                "events_processed_total",
+                "Number of the events processed by the handler",
+                &["handler"],
+                registry
+            )?,
+            events_failed: register_int_counter_vec_with_registry!(
+                "events_failed_total",
+                "Number of the failed attempts to process the event by the handler",
+                &["handler"],
+                registry
+            )?,
+            handler_duration: register_histogram_vec_with_registry!(
+                "handler_duration_seconds",
+                "Duration of the event processing by the handler",
+                &["handler"],
+                registry
+            )?,
+            icp_duration: register_histogram_vec_with_registry!(
+                "icp_query_duration_seconds",
+                "Duration of the ICP queries",
+                &["method"],
+                registry
+            )?,
+            icp_errors: register_int_counter_vec_with_registry!(
+                "icp_query_errors_total",
+                "Number of the failed ICP queries",
+                &["method"],
+                registry
+            )?,
+            icp_retries: register_int_counter_vec_with_registry!(
+                "icp_query_retries_total",
+                "Number of the retried ICP queries",
+                &["method"],
+                registry
+            )?,
+            icp_circuit_state: register_int_gauge_with_registry!(
+                "icp_circuit_state",
+                "State of the ICP circuit breaker, 0 closed, 1 half-open, 2 open",
+                registry
+            )?,
+            matrix_errors: register_int_counter_vec_with_registry!(
+                "matrix_errors_total",
+                "Number of the failed Matrix API requests",
+                &["endpoint"],
+                registry
+            )?,
+            task_restarts: register_int_counter_vec_with_registry!(
+                "task_restarts_total",
+                "Number of the restarts of the supervised task",
+                &["task"],
+                registry
+            )?,
+            task_failures: register_int_gauge_vec_with_registry!(
+                "task_consecutive_failures",
+                "Number of the failures of the supervised task in a row",
+                &["task"],
+                registry
+            )?,
+            task_last_failure: register_int_gauge_vec_with_registry!(
+                "task_last_failure_timestamp_seconds",
+                "Unix time of the last failure of the supervised task",
+                &["task"],
+                registry
+            )?,
+            registry,
+        })
+    }
+
+    pub fn set_producer_lag(&self, lag: u64) {
+        self.producer_lag.set(lag as i64);
+    }
+
+    pub fn set_producer_mode(&self, mode: &str) {
+        for m in MODES {
+            self.producer_mode
+                .with_label_values(&[m])
+                .set((m == mode) as i64);
+        }
+    }
+
+    pub fn set_queue_depth(&self, queue: &str, depth: u64, dead_letters: u64) {
+        self.queue_depth
+            .with_label_values(&[queue])
+            .set(depth as i64);
+        self.dead_letter_depth
+            .with_label_values(&[queue])
+            .set(dead_letters as i64);
+    }
+
+    pub fn observe_handler(&self, handler: &str, duration: Duration, ok: bool) {
+        self.handler_duration
+            .with_label_values(&[handler])
+            .observe(duration.as_secs_f64());
+
+        match ok {
+            true => self.events_processed.with_label_values(&[handler]).inc(),
+            false => self.events_failed.with_label_values(&[handler]).inc(),
+        }
+    }
+
+    pub fn observe_icp_query(&self, method: &str, duration: Duration, ok: bool) {
+        self.icp_duration
+            .with_label_values(&[method])
+            .observe(duration.as_secs_f64());
+
+        if !ok {
+            self.icp_errors.with_label_values(&[method]).inc();
+        }
+    }
+
+    pub fn icp_retry(&self, method: &str) {
+        self.icp_retries.with_label_values(&[method]).inc();
+    }
+
+    pub fn set_icp_circuit_state(&self, state: CircuitState) {
+        let value = match state {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        };
+
+        self.icp_circuit_state.set(value);
+    }
+
+    pub fn matrix_error(&self, endpoint: &str) {
+        self.matrix_errors.with_label_values(&[endpoint]).inc();
+    }
+
+    pub fn task_failed(&self, task: &str, failures: u32) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        self.task_failures
+            .with_label_values(&[task])
+            .set(failures as i64);
+        self.task_last_failure
+            .with_label_values(&[task])
+            .set(now.as_secs() as i64);
+    }
+
+    pub fn task_restarted(&self, task: &str) {
+        self.task_restarts.with_label_values(&[task]).inc();
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> eyre::Result<String> {
+        let mut buf = vec![];
+
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .wrap_err("Failed to encode metrics")?;
+
+        String::from_utf8(buf).wrap_err("Metrics are not valid utf-8")
+    }
+}
@@ -1,31 +1,42 @@
-use std::{str::FromStr, sync::Arc, time::Duration};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use eyre::Context as _;
 
-use crate::{consumer::QueueKey, context::Context, data};
+use crate::{consumer::QueueKey, context::Context, icp, utils::trace};
 
 const INITIAL_HISTORY_POINT: u64 = 1;
 
+/// Interval of refreshing the history point of the proxy canister, so the producer lag is actual
+/// during a long catchup as well.
+const REMOTE_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
 pub async fn run(ctx: Arc<Context>) -> eyre::Result<()> {
     tracing::info!("Starting producer...");
-    tracing::debug!("Trying to get history point from the redis");
+    tracing::debug!("Trying to get history point from the store");
 
-    let last = data::get_history_point(ctx.clone())
+    let last = ctx
+        .store()
+        .get_history_point()
         .await
         .wrap_err("Failed to get history point during the catchup")?;
 
     tracing::debug!("Trying to get history point from the ICP");
 
-    let actual = ctx
-        .clone()
-        .icp()
-        .get_history_point()
-        .await
-        .wrap_err("Failed to get history point from ICP")?;
+    let Some(actual) = get_remote_history_point(&ctx).await? else {
+        tracing::info!("Stopping producer...");
+        return Ok(());
+    };
+
+    ctx.health().set_remote_history_point(actual);
 
     let last = if ctx.config().skip_catchup {
         tracing::info!("Skipping catchup, starting listening events...");
-        data::set_history_point(ctx.clone(), actual)
+        ctx.store()
+            .set_history_point(actual)
             .await
             .wrap_err("Failed to set actual history point during the skipping catchup")?;
         actual
@@ -44,6 +55,32 @@ pub async fn run(ctx: Arc<Context>) -> eyre::Result<()> {
         .wrap_err("Failed to produce events")
 }
 
+/// Gets the history point of the proxy canister, pausing while the IC is unavailable as the polling
+/// of the events does. Returns `None` if the shutdown interrupts the pause.
+async fn get_remote_history_point(ctx: &Context) -> eyre::Result<Option<u64>> {
+    let interval = Duration::from_millis(ctx.config().interval);
+
+    loop {
+        match ctx.icp().get_history_point().await {
+            Ok(actual) => return Ok(Some(actual)),
+            Err(e) if icp::is_unavailable(&e) => {
+                let pause = ctx.icp().circuit_retry_in().unwrap_or(interval);
+
+                tracing::warn!(
+                    err = format!("{e:#}"),
+                    "ICP is unavailable, pausing for {}ms",
+                    pause.as_millis()
+                );
+
+                if !ctx.sleep(pause).await {
+                    return Ok(None);
+                }
+            }
+            Err(e) => return Err(e).wrap_err("Failed to get history point from ICP"),
+        }
+    }
+}
+
 async fn get_last_history_point(
     ctx: Arc<Context>,
     actual: u64,
@@ -61,11 +98,12 @@ async fn get_last_history_point(
 
     tracing::info!("History point is not set, starting catchup from initial history point...");
 
-    data::set_history_point(ctx.clone(), INITIAL_HISTORY_POINT)
+    ctx.store()
+        .set_history_point(INITIAL_HISTORY_POINT)
         .await
         .wrap_err("Failed to set initial history point during the catchup")?;
 
-    tracing::debug!("History point is set successfully to redis");
+    tracing::debug!("History point is set successfully to the store");
     Ok(INITIAL_HISTORY_POINT)
 }
 
@@ -78,46 +116,243 @@ async fn produce_events(ctx: Arc<Context>, start_from: u64, actual: u64) -> eyre
         false => "catchup",
     };
 
+    ctx.metrics().set_producer_mode(mode);
+    ctx.health().set_catchup(mode == "catchup");
+
+    let mut refreshed_at = Instant::now();
+
     loop {
         let ctx = ctx.clone();
 
+        if ctx.is_shutting_down() {
+            tracing::info!(mode, history_point, "Stopping producer...");
+            return Ok(());
+        }
+
+        if refreshed_at.elapsed() >= REMOTE_REFRESH_INTERVAL {
+            refresh_remote_history_point(&ctx).await;
+            refreshed_at = Instant::now();
+        }
+
         tracing::debug!(mode, history_point, "Getting events...",);
 
-        let events = ctx
-            .icp()
-            .get_events(history_point)
-            .await
-            .wrap_err_with(|| format!("Failed to get event on history_point: {history_point}"))?;
+        let events = match ctx.icp().get_events(history_point).await {
+            Ok(events) => events,
+            // Polling is paused until the IC is back, instead of restarting the producer
+            Err(e) if icp::is_unavailable(&e) => {
+                let pause = ctx.icp().circuit_retry_in().unwrap_or(interval);
+
+                tracing::warn!(
+                    mode,
+                    history_point,
+                    err = format!("{e:#}"),
+                    "ICP is unavailable, pausing for {}ms",
+                    pause.as_millis()
+                );
+
+                // Waiting for the IC is not a stalled catchup, restarting the relayer won't help
+                ctx.health().touch_producer();
+                ctx.sleep(pause).await;
+                continue;
+            }
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("Failed to get event on history_point: {history_point}")
+                })
+            }
+        };
 
         tracing::debug!(mode, history_point, "Got {} events", events.len());
 
         if events.is_empty() {
+            // No events after the history point, so it is the actual one of the proxy canister
+            ctx.health().set_remote_history_point(history_point);
             tracing::debug!(history_point, "No more events to produce, sleeping...");
-            tokio::time::sleep(interval).await;
+            ctx.sleep(interval).await;
             continue;
         }
 
-        for event in events.clone() {
-            let key = QueueKey::from_str(&event.1.kind)?;
-            data::queue_event(ctx.clone(), key, event.clone()).await?;
-        }
+        let batch = events
+            .iter()
+            .map(|event| {
+                let key = QueueKey::from_str(&event.1.kind)?;
+                // The trace of the event is continued by the consumer
+                let span = tracing::info_span!(
+                    "produce_event",
+                    history_point = event.0,
+                    kind = event.1.kind
+                );
+                Ok((key, event.clone(), trace::inject(&span)))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
 
         history_point = events.last().expect("events is not empty").0 + 1;
 
-        data::set_history_point(ctx, history_point)
+        let queued = ctx
+            .store()
+            .commit_events(batch, history_point)
             .await
-            .wrap_err("Failed to set history point after the producing events")?;
+            .wrap_err("Failed to commit events and history point after the producing events")?;
+
+        if queued < events.len() {
+            tracing::warn!(
+                mode,
+                history_point,
+                "Skipped {} already queued event(s)",
+                events.len() - queued
+            );
+        }
 
         tracing::debug!(
             mode,
             history_point,
-            "History point is set successfully to redis"
+            "History point is set successfully to the store"
         );
 
-        tracing::info!(mode, history_point, "Produced {} event(s)", events.len());
+        tracing::info!(mode, history_point, "Produced {} event(s)", queued);
+
+        ctx.health().set_remote_history_point(history_point);
+        ctx.health().touch_producer();
 
         if history_point >= actual && mode == "catchup" {
             mode = "listening";
+            ctx.metrics().set_producer_mode(mode);
+            ctx.health().set_catchup(false);
+        }
+    }
+}
+
+/// The failure is not fatal, the history point is refreshed again on the next interval.
+async fn refresh_remote_history_point(ctx: &Context) {
+    match ctx.icp().get_history_point().await {
+        Ok(remote) => ctx.health().set_remote_history_point(remote),
+        Err(e) => tracing::warn!(
+            err = format!("{e:#}"),
+            "Failed to refresh history point of the proxy canister"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proxy_types::models::history_event::HistoryEventKind;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{icp::mock::MockIcp, testing};
+
+    /// The producer doesn't decode the payloads, so any payload will do.
+    fn mock_icp(events: u64) -> Arc<MockIcp> {
+        let icp = Arc::new(MockIcp::new(2));
+
+        for group_id in 1..=events {
+            icp.push_event(HistoryEventKind::GroupCreated, &group_id);
         }
+
+        icp
+    }
+
+    async fn queued(ctx: &Context) -> u64 {
+        ctx.store()
+            .queue_len(QueueKey::from(HistoryEventKind::GroupCreated))
+            .await
+            .unwrap()
+    }
+
+    async fn has_history_point(ctx: &Context, expected: u64) -> bool {
+        ctx.store().get_history_point().await.unwrap() == Some(expected)
+    }
+
+    #[tokio::test]
+    async fn test_catchup_then_listening() {
+        let icp = mock_icp(5);
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), icp.clone()).await;
+        let producer = tokio::spawn(run(ctx.clone()));
+
+        testing::eventually(|| has_history_point(&ctx, 6)).await;
+        assert_eq!(queued(&ctx).await, 5);
+        assert!(ctx.health().catchup_stalled_for().is_none());
+
+        icp.push_event(HistoryEventKind::GroupCreated, &6u64);
+
+        testing::eventually(|| has_history_point(&ctx, 7)).await;
+        assert_eq!(queued(&ctx).await, 6);
+        assert_eq!(ctx.health().remote_history_point(), Some(7));
+
+        ctx.shutdown().cancel();
+        producer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_catchup_from_stored_history_point() {
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), mock_icp(5)).await;
+        ctx.store().set_history_point(4).await.unwrap();
+
+        let producer = tokio::spawn(run(ctx.clone()));
+
+        testing::eventually(|| has_history_point(&ctx, 6)).await;
+        assert_eq!(queued(&ctx).await, 2);
+
+        ctx.shutdown().cancel();
+        producer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_skip_catchup() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.skip_catchup = true;
+
+        let ctx = testing::context(cfg, mock_icp(5)).await;
+        let producer = tokio::spawn(run(ctx.clone()));
+
+        testing::eventually(|| has_history_point(&ctx, 6)).await;
+        assert_eq!(queued(&ctx).await, 0);
+
+        ctx.shutdown().cancel();
+        producer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pauses_while_icp_is_unavailable() {
+        let icp = mock_icp(1);
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), icp.clone()).await;
+        let producer = tokio::spawn(run(ctx.clone()));
+
+        testing::eventually(|| has_history_point(&ctx, 2)).await;
+
+        icp.set_unavailable(true);
+        icp.push_event(HistoryEventKind::GroupCreated, &2u64);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!producer.is_finished());
+
+        icp.set_unavailable(false);
+        testing::eventually(|| has_history_point(&ctx, 3)).await;
+
+        ctx.shutdown().cancel();
+        producer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_icp_on_start() {
+        let icp = mock_icp(1);
+        icp.set_unavailable(true);
+
+        let dir = TempDir::new().unwrap();
+        let ctx = testing::context(testing::config(&dir), icp.clone()).await;
+        let producer = tokio::spawn(run(ctx.clone()));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!producer.is_finished());
+        assert_eq!(ctx.health().remote_history_point(), None);
+
+        icp.set_unavailable(false);
+        testing::eventually(|| has_history_point(&ctx, 2)).await;
+
+        ctx.shutdown().cancel();
+        producer.await.unwrap().unwrap();
     }
 }
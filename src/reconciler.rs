@@ -0,0 +1,327 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use candid::Principal;
+use eyre::Context as _;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, UserId};
+use serde::Serialize;
+
+use crate::{
+    consumer::group_room_ids,
+    context::Context,
+    matrix::{get_member_power_levels, set_member_power_level},
+    types::{resolve_power_level, MatrixUserID},
+};
+
+/// Difference between the power level of the member in the room and the role in the group.
+#[derive(Debug, Clone, Serialize)]
+pub struct Drift {
+    pub room_id: OwnedRoomId,
+    pub user_id: OwnedUserId,
+    pub actual: i64,
+    pub expected: u64,
+}
+
+pub async fn run(ctx: Arc<Context>) -> eyre::Result<()> {
+    let cfg = ctx.config();
+
+    if cfg.reconcile_interval == 0 {
+        tracing::info!("Reconciler is disabled");
+        ctx.shutdown().cancelled().await;
+        return Ok(());
+    }
+
+    tracing::info!(dry_run = cfg.reconcile_dry_run, "Starting reconciler...");
+    let interval = Duration::from_millis(cfg.reconcile_interval);
+
+    loop {
+        if !ctx.sleep(interval).await {
+            tracing::info!("Stopping reconciler...");
+            return Ok(());
+        }
+
+        let drifts = reconcile_all(ctx.clone())
+            .await
+            .wrap_err("Failed to reconcile groups")?;
+
+        tracing::info!(
+            dry_run = cfg.reconcile_dry_run,
+            "Reconciled groups, found {} drift(s)",
+            drifts
+        );
+    }
+}
+
+/// Walks all of the groups in the proxy canister, returns the total number of drifts.
+async fn reconcile_all(ctx: Arc<Context>) -> eyre::Result<usize> {
+    let mut page = 1;
+    let mut drifts = 0;
+
+    loop {
+        let groups = ctx
+            .icp()
+            .get_groups(page)
+            .await
+            .wrap_err_with(|| format!("Failed to get groups page {page}"))?;
+
+        for group in groups.groups.iter() {
+            // One broken group shouldn't stop the rest from being reconciled
+            match reconcile_group(ctx.clone(), group.id).await {
+                Ok(group_drifts) => drifts += group_drifts.len(),
+                Err(e) => tracing::warn!(
+                    group_id = group.id,
+                    error = format!("{e:#}"),
+                    "Failed to reconcile group"
+                ),
+            }
+        }
+
+        if page >= groups.number_of_pages || groups.groups.is_empty() {
+            return Ok(drifts);
+        }
+
+        page += 1;
+    }
+}
+
+/// Compares the power levels in the space and rooms of the group with the roles of its members
+/// and applies the differences, unless the reconciler is in the dry-run mode. The elevated power
+/// levels of the users who are not members of the group anymore are reset to the default one.
+pub async fn reconcile_group(ctx: Arc<Context>, group_id: u64) -> eyre::Result<Vec<Drift>> {
+    let cfg = ctx.config();
+
+    let members = ctx
+        .icp()
+        .get_group_members(group_id)
+        .await
+        .wrap_err_with(|| format!("Failed to get members of the group {group_id}"))?;
+
+    let principals: HashSet<Principal> = members.iter().map(|member| member.principal).collect();
+
+    let expected: HashMap<Principal, u64> = members
+        .into_iter()
+        .filter_map(|member| {
+            let expected = resolve_power_level(&cfg, group_id, &member.roles)?;
+            Some((member.principal, expected))
+        })
+        .collect();
+
+    let Some(room_ids) = group_room_ids(ctx.clone(), None, group_id).await? else {
+        return Ok(vec![]);
+    };
+
+    let mut drifts = vec![];
+
+    for room_id in room_ids {
+        let Some((users_default, levels)) =
+            get_member_power_levels(ctx.clone(), room_id.clone()).await?
+        else {
+            continue;
+        };
+
+        for (user_id, actual) in levels {
+            let Some(principal) = principal_of(&user_id) else {
+                continue;
+            };
+
+            let expected = match expected.get(&principal) {
+                Some(expected) => *expected,
+                None if !principals.contains(&principal) && actual > users_default => {
+                    users_default.max(0) as u64
+                }
+                // A member whose roles are not mapped, or a former one without elevated level
+                None => continue,
+            };
+
+            if actual == expected as i64 {
+                continue;
+            }
+
+            drifts.push(Drift {
+                room_id: room_id.clone(),
+                user_id,
+                actual,
+                expected,
+            });
+        }
+    }
+
+    for drift in drifts.iter() {
+        tracing::info!(
+            dry_run = cfg.reconcile_dry_run,
+            group_id,
+            room_id = drift.room_id.to_string(),
+            user_id = drift.user_id.to_string(),
+            actual = drift.actual,
+            expected = drift.expected,
+            "Power level drift"
+        );
+
+        if cfg.reconcile_dry_run {
+            continue;
+        }
+
+        set_member_power_level(
+            ctx.clone(),
+            drift.room_id.clone(),
+            matrix_user_id(&drift.user_id)?,
+            drift.expected,
+        )
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to fix power level, member: \"{}\", room: \"{}\", level: \"{}\"",
+                drift.user_id, drift.room_id, drift.expected
+            )
+        })?;
+    }
+
+    Ok(drifts)
+}
+
+/// Matrix user ids of the platform users are `@{principal}/{username}:{server}`.
+fn principal_of(user_id: &UserId) -> Option<Principal> {
+    let (principal, _) = user_id.localpart().split_once('/')?;
+    Principal::from_text(principal).ok()
+}
+
+fn matrix_user_id(user_id: &UserId) -> eyre::Result<MatrixUserID> {
+    let (principal, username) = user_id
+        .localpart()
+        .split_once('/')
+        .ok_or_else(|| eyre::eyre!("Not a platform user id: {user_id}"))?;
+
+    let principal = Principal::from_text(principal)
+        .wrap_err_with(|| format!("Failed to parse principal of the user id: {user_id}"))?;
+
+    Ok(MatrixUserID::new(
+        principal,
+        username.to_owned(),
+        user_id.server_name().to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        icp::mock::MockIcp,
+        testing::{self, Homeserver},
+        types::{Group, GroupMember},
+    };
+
+    const GROUP_ID: u64 = 1;
+
+    /// The member is an admin of the group, but has the member power level in the space.
+    async fn drifted_context(
+        homeserver: &Homeserver,
+        dir: &TempDir,
+        dry_run: bool,
+    ) -> (Arc<Context>, String) {
+        drifted_context_with(homeserver, dir, dry_run, &[]).await
+    }
+
+    /// Same as `drifted_context`, the other users are joined to the space with the power levels.
+    async fn drifted_context_with(
+        homeserver: &Homeserver,
+        dir: &TempDir,
+        dry_run: bool,
+        others: &[(String, i64)],
+    ) -> (Arc<Context>, String) {
+        let space_id = homeserver.room_id("space");
+        let member = MatrixUserID::new(
+            Principal::anonymous(),
+            "alice".to_owned(),
+            homeserver.server_name(),
+        );
+
+        let mut power_levels = vec![(member.to_string(), 10)];
+        power_levels.extend_from_slice(others);
+
+        homeserver
+            .sync_with_power_levels(&[space_id.clone()], &[], &power_levels)
+            .await;
+        homeserver.hierarchy(&space_id, &[]).await;
+
+        let icp = MockIcp::new(100);
+        icp.set_group(
+            GROUP_ID,
+            Group {
+                matrix_space_id: space_id,
+                ..Default::default()
+            },
+        );
+        icp.set_group_members(
+            GROUP_ID,
+            vec![GroupMember {
+                principal: Principal::anonymous(),
+                roles: vec!["admin".to_owned()],
+            }],
+        );
+
+        let mut cfg = testing::config(dir);
+        cfg.reconcile_dry_run = dry_run;
+
+        let ctx = testing::synced_context(homeserver, cfg, Arc::new(icp)).await;
+        (ctx, member.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_fixes_drift() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let (ctx, member) = drifted_context(&homeserver, &dir, false).await;
+
+        assert_eq!(reconcile_all(ctx).await.unwrap(), 1);
+
+        let events = homeserver.state_events("m.room.power_levels").await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, homeserver.room_id("space"));
+        assert_eq!(events[0].1["users"][member], 95);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_resets_former_member() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+
+        // Still joined to the space with the moderator power level, but not in the group anymore
+        let former = MatrixUserID::new(
+            Principal::management_canister(),
+            "bob".to_owned(),
+            homeserver.server_name(),
+        );
+        let (ctx, _) =
+            drifted_context_with(&homeserver, &dir, true, &[(former.to_string(), 50)]).await;
+
+        let drifts = reconcile_group(ctx, GROUP_ID).await.unwrap();
+        let drift = drifts
+            .iter()
+            .find(|drift| drift.user_id.as_str() == former.to_string())
+            .unwrap();
+        assert_eq!((drift.actual, drift.expected), (50, 0));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_dry_run_only_reports_drift() {
+        let dir = TempDir::new().unwrap();
+        let homeserver = Homeserver::start().await;
+        let (ctx, member) = drifted_context(&homeserver, &dir, true).await;
+
+        let drifts = reconcile_group(ctx.clone(), GROUP_ID).await.unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].user_id.as_str(), member);
+        assert_eq!((drifts[0].actual, drifts[0].expected), (10, 95));
+
+        assert_eq!(reconcile_all(ctx).await.unwrap(), 1);
+        assert!(homeserver
+            .state_events("m.room.power_levels")
+            .await
+            .is_empty());
+    }
+}
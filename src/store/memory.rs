@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use eyre::{Context as _, OptionExt};
+use proxy_types::models::history_event::HistoryEventEntry;
+
+use super::{DeadLetter, EventStore, QueuedEvent};
+use crate::{config::Config, consumer::QueueKey, utils::trace::TraceContext};
+
+/// Non-persistent store, intended for tests.
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+    claim_timeout: Duration,
+    limit: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    history_point: Option<u64>,
+    next_id: u64,
+    queues: HashMap<String, Vec<Entry>>,
+    dead_letters: HashMap<String, Vec<DeadLetter>>,
+    group_spaces: HashMap<u64, String>,
+    media: HashMap<String, String>,
+    matrix_sessions: HashMap<String, String>,
+}
+
+struct Entry {
+    id: u64,
+    event: HistoryEventEntry,
+    trace: TraceContext,
+    attempts: u64,
+    claimed_at: Option<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            inner: Mutex::default(),
+            claim_timeout: Duration::from_millis(cfg.claim_timeout),
+            limit: cfg.limit,
+        }
+    }
+
+    fn lock(&self) -> eyre::Result<std::sync::MutexGuard<'_, Inner>> {
+        self.inner
+            .lock()
+            .map_err(|_| eyre::eyre!("Memory store mutex is poisoned"))
+    }
+}
+
+#[async_trait]
+impl EventStore for MemoryStore {
+    async fn get_history_point(&self) -> eyre::Result<Option<u64>> {
+        Ok(self.lock()?.history_point)
+    }
+
+    async fn set_history_point(&self, point: u64) -> eyre::Result<()> {
+        self.lock()?.history_point = Some(point);
+        Ok(())
+    }
+
+    async fn commit_events(
+        &self,
+        events: Vec<(QueueKey, HistoryEventEntry, TraceContext)>,
+        history_point: u64,
+    ) -> eyre::Result<usize> {
+        let mut inner = self.lock()?;
+        let current = inner.history_point.unwrap_or_default();
+        let mut queued = 0;
+
+        for (key, event, trace) in events {
+            if event.0 < current {
+                continue;
+            }
+
+            inner.next_id += 1;
+            let id = inner.next_id;
+
+            inner
+                .queues
+                .entry(key.to_string())
+                .or_default()
+                .push(Entry {
+                    id,
+                    event,
+                    trace,
+                    attempts: 0,
+                    claimed_at: None,
+                });
+
+            queued += 1;
+        }
+
+        inner.history_point = Some(history_point);
+        Ok(queued)
+    }
+
+    async fn prepare_queue(&self, _key: QueueKey) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn get_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let mut inner = self.lock()?;
+        let now = Instant::now();
+
+        let Some(queue) = inner.queues.get_mut(&key.to_string()) else {
+            return Ok(vec![]);
+        };
+
+        let events = queue
+            .iter_mut()
+            .filter(|entry| match entry.claimed_at {
+                Some(claimed_at) => now.duration_since(claimed_at) >= self.claim_timeout,
+                None => true,
+            })
+            .take(self.limit as usize)
+            .map(|entry| {
+                entry.claimed_at = Some(now);
+                QueuedEvent {
+                    id: entry.id.to_string(),
+                    entry: entry.event.clone(),
+                    trace: entry.trace.clone(),
+                }
+            })
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn get_pending_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let inner = self.lock()?;
+
+        let Some(queue) = inner.queues.get(&key.to_string()) else {
+            return Ok(vec![]);
+        };
+
+        let events = queue
+            .iter()
+            .filter(|entry| entry.claimed_at.is_some())
+            .map(|entry| QueuedEvent {
+                id: entry.id.to_string(),
+                entry: entry.event.clone(),
+                trace: entry.trace.clone(),
+            })
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn is_queued(&self, key: QueueKey, id: &str) -> eyre::Result<bool> {
+        let id: u64 = id.parse().wrap_err("Invalid memory event id")?;
+
+        Ok(self
+            .lock()?
+            .queues
+            .get(&key.to_string())
+            .is_some_and(|queue| queue.iter().any(|entry| entry.id == id)))
+    }
+
+    async fn ack_event(&self, key: QueueKey, id: &str) -> eyre::Result<()> {
+        let id: u64 = id.parse().wrap_err("Invalid memory event id")?;
+
+        if let Some(queue) = self.lock()?.queues.get_mut(&key.to_string()) {
+            queue.retain(|entry| entry.id != id);
+        }
+
+        Ok(())
+    }
+
+    async fn incr_attempts(&self, key: QueueKey, id: &str) -> eyre::Result<u64> {
+        let id: u64 = id.parse().wrap_err("Invalid memory event id")?;
+        let mut inner = self.lock()?;
+
+        let entry = inner
+            .queues
+            .get_mut(&key.to_string())
+            .and_then(|queue| queue.iter_mut().find(|entry| entry.id == id))
+            .ok_or_eyre("Event not found in the queue")?;
+
+        entry.attempts += 1;
+        Ok(entry.attempts)
+    }
+
+    async fn dead_letter(
+        &self,
+        key: QueueKey,
+        event: QueuedEvent,
+        attempts: u64,
+        errors: Vec<String>,
+    ) -> eyre::Result<()> {
+        self.ack_event(key.clone(), &event.id).await?;
+
+        self.lock()?
+            .dead_letters
+            .entry(key.dead_letter())
+            .or_default()
+            .push(DeadLetter {
+                id: event.id,
+                entry: event.entry,
+                attempts,
+                errors,
+            });
+
+        Ok(())
+    }
+
+    async fn get_group_space(&self, group_id: u64) -> eyre::Result<Option<String>> {
+        Ok(self.lock()?.group_spaces.get(&group_id).cloned())
+    }
+
+    async fn set_group_space(&self, group_id: u64, space_id: String) -> eyre::Result<()> {
+        self.lock()?.group_spaces.insert(group_id, space_id);
+        Ok(())
+    }
+
+    async fn get_media(&self, url: &str) -> eyre::Result<Option<String>> {
+        Ok(self.lock()?.media.get(url).cloned())
+    }
+
+    async fn set_media(&self, url: &str, mxc: String) -> eyre::Result<()> {
+        self.lock()?.media.insert(url.to_owned(), mxc);
+        Ok(())
+    }
+
+    async fn get_matrix_session(&self, replica: &str) -> eyre::Result<Option<String>> {
+        Ok(self.lock()?.matrix_sessions.get(replica).cloned())
+    }
+
+    async fn set_matrix_session(&self, replica: &str, session: String) -> eyre::Result<()> {
+        self.lock()?
+            .matrix_sessions
+            .insert(replica.to_owned(), session);
+        Ok(())
+    }
+
+    async fn queue_event(
+        &self,
+        key: QueueKey,
+        event: HistoryEventEntry,
+        trace: TraceContext,
+    ) -> eyre::Result<()> {
+        let mut inner = self.lock()?;
+
+        inner.next_id += 1;
+        let id = inner.next_id;
+
+        inner
+            .queues
+            .entry(key.to_string())
+            .or_default()
+            .push(Entry {
+                id,
+                event,
+                trace,
+                attempts: 0,
+                claimed_at: None,
+            });
+
+        Ok(())
+    }
+
+    async fn queue_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        let inner = self.lock()?;
+        Ok(inner.queues.get(&key.to_string()).map_or(0, Vec::len) as u64)
+    }
+
+    async fn dead_letter_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        let inner = self.lock()?;
+        Ok(inner
+            .dead_letters
+            .get(&key.dead_letter())
+            .map_or(0, Vec::len) as u64)
+    }
+
+    async fn get_dead_letters(&self, key: QueueKey, limit: u64) -> eyre::Result<Vec<DeadLetter>> {
+        let inner = self.lock()?;
+
+        let dead_letters = inner
+            .dead_letters
+            .get(&key.dead_letter())
+            .map(|dead_letters| dead_letters.iter().take(limit as usize).cloned().collect())
+            .unwrap_or_default();
+
+        Ok(dead_letters)
+    }
+
+    async fn ping(&self) -> eyre::Result<()> {
+        self.lock().map(|_| ())
+    }
+}
@@ -0,0 +1,537 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use proxy_types::models::history_event::HistoryEventEntry;
+
+use crate::{
+    config::{Config, StoreKind},
+    consumer::QueueKey,
+    utils::trace::TraceContext,
+};
+
+mod memory;
+mod redis;
+mod sqlite;
+
+pub use self::redis::RedisStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// Event read from the queue, `id` is the backend specific id which is used to acknowledge it.
+#[derive(Debug, Clone)]
+pub struct QueuedEvent {
+    pub id: String,
+    pub entry: HistoryEventEntry,
+    pub trace: TraceContext,
+}
+
+/// Event which failed to be processed `max_attempts` times, `errors` is the error chain of the last
+/// attempt.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub id: String,
+    pub entry: HistoryEventEntry,
+    pub attempts: u64,
+    pub errors: Vec<String>,
+}
+
+/// Persistence of the history point and the event queues.
+///
+/// Events are delivered at least once: an event returned by [`EventStore::get_events`] which is
+/// not acknowledged within the claim timeout is returned again.
+#[async_trait]
+pub trait EventStore: Send + Sync {
+    async fn get_history_point(&self) -> eyre::Result<Option<u64>>;
+
+    async fn set_history_point(&self, point: u64) -> eyre::Result<()>;
+
+    /// Atomically queues the batch of events and advances the history point to `history_point`.
+    /// Events below the currently stored history point are already queued and are skipped, so
+    /// committing the same batch twice is a no-op. Returns the number of queued events.
+    async fn commit_events(
+        &self,
+        events: Vec<(QueueKey, HistoryEventEntry, TraceContext)>,
+        history_point: u64,
+    ) -> eyre::Result<usize>;
+
+    /// Prepares the queue to be consumed, e.g. creates the consumer group.
+    async fn prepare_queue(&self, key: QueueKey) -> eyre::Result<()>;
+
+    /// Returns up to `limit` events, the stale unacknowledged events go first.
+    async fn get_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>>;
+
+    /// Returns the events which are claimed and not acknowledged yet, in the queue order, without
+    /// claiming them again. The consumer keeps the order of their groups after a restart with it.
+    async fn get_pending_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>>;
+
+    /// Returns `true` while the event is in the queue, i.e. it is neither acknowledged nor moved to
+    /// the dead letter queue, by this or by another consumer.
+    async fn is_queued(&self, key: QueueKey, id: &str) -> eyre::Result<bool>;
+
+    /// Acknowledges the event and removes it from the queue.
+    async fn ack_event(&self, key: QueueKey, id: &str) -> eyre::Result<()>;
+
+    /// Increments the processing attempts counter of the event, returns the new value.
+    async fn incr_attempts(&self, key: QueueKey, id: &str) -> eyre::Result<u64>;
+
+    /// Moves the event to the dead letter queue together with the error chain of the last attempt
+    /// and acknowledges it in the original queue.
+    async fn dead_letter(
+        &self,
+        key: QueueKey,
+        event: QueuedEvent,
+        attempts: u64,
+        errors: Vec<String>,
+    ) -> eyre::Result<()>;
+
+    /// Returns the Matrix space provisioned by the relayer for the group.
+    async fn get_group_space(&self, group_id: u64) -> eyre::Result<Option<String>>;
+
+    async fn set_group_space(&self, group_id: u64, space_id: String) -> eyre::Result<()>;
+
+    /// Returns the `mxc://` uri of the image downloaded from the url and uploaded to the media
+    /// repository of the homeserver.
+    async fn get_media(&self, url: &str) -> eyre::Result<Option<String>>;
+
+    async fn set_media(&self, url: &str, mxc: String) -> eyre::Result<()>;
+
+    /// Returns the serialized Matrix session of the relayer replica. Each replica has its own
+    /// device and access token, so the replicas sharing the store don't log each other out.
+    async fn get_matrix_session(&self, replica: &str) -> eyre::Result<Option<String>>;
+
+    async fn set_matrix_session(&self, replica: &str, session: String) -> eyre::Result<()>;
+
+    /// Queues the event without touching the history point, used for replaying the events.
+    async fn queue_event(
+        &self,
+        key: QueueKey,
+        event: HistoryEventEntry,
+        trace: TraceContext,
+    ) -> eyre::Result<()>;
+
+    /// Returns the number of the events in the queue, including the unacknowledged ones.
+    async fn queue_len(&self, key: QueueKey) -> eyre::Result<u64>;
+
+    /// Returns the number of the events in the dead letter queue.
+    async fn dead_letter_len(&self, key: QueueKey) -> eyre::Result<u64>;
+
+    /// Returns up to `limit` oldest events of the dead letter queue.
+    async fn get_dead_letters(&self, key: QueueKey, limit: u64) -> eyre::Result<Vec<DeadLetter>>;
+
+    /// Checks that the store backend is reachable.
+    async fn ping(&self) -> eyre::Result<()>;
+}
+
+pub async fn from_cfg(cfg: &Config) -> eyre::Result<Arc<dyn EventStore>> {
+    let store: Arc<dyn EventStore> = match cfg.store {
+        StoreKind::Redis => Arc::new(RedisStore::new(cfg).await?),
+        StoreKind::Sqlite => Arc::new(SqliteStore::new(cfg).await?),
+        StoreKind::Memory => Arc::new(MemoryStore::new(cfg)),
+    };
+
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use proxy_types::models::history_event::HistoryEventKind;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{icp::mock, testing};
+
+    const KIND: HistoryEventKind = HistoryEventKind::GroupCreated;
+    const CLAIM_TIMEOUT: u64 = 100;
+
+    fn key() -> QueueKey {
+        QueueKey::from(KIND)
+    }
+
+    fn entry(history_point: u64) -> (QueueKey, HistoryEventEntry, TraceContext) {
+        let event = mock::event(KIND, &history_point);
+        (key(), (history_point, event), TraceContext::new())
+    }
+
+    fn entries(
+        history_points: impl IntoIterator<Item = u64>,
+    ) -> Vec<(QueueKey, HistoryEventEntry, TraceContext)> {
+        history_points.into_iter().map(entry).collect()
+    }
+
+    fn history_points(events: &[QueuedEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.entry.0).collect()
+    }
+
+    fn config(dir: &TempDir) -> Config {
+        let mut cfg = testing::config(dir);
+        cfg.claim_timeout = CLAIM_TIMEOUT;
+        cfg.sqlite_path = dir.path().join("relayer.db").to_string_lossy().into_owned();
+        cfg
+    }
+
+    /// The database is removed together with the directory, so it has to outlive the store.
+    async fn sqlite(dir: &TempDir) -> Arc<dyn EventStore> {
+        Arc::new(SqliteStore::new(&config(dir)).await.unwrap())
+    }
+
+    fn memory(dir: &TempDir) -> Arc<dyn EventStore> {
+        Arc::new(MemoryStore::new(&config(dir)))
+    }
+
+    /// Redis of the `TEST_REDIS_URL`, local by default. The keys of the store are fixed, so each
+    /// test takes its own database, which is flushed first.
+    async fn redis(dir: &TempDir, db: u8) -> Arc<dyn EventStore> {
+        let url =
+            std::env::var("TEST_REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_owned());
+
+        let mut cfg = config(dir);
+        cfg.redis_url = format!("{}/{db}", url.trim_end_matches('/'));
+
+        let mut conn = ::redis::Client::open(cfg.redis_url.clone())
+            .unwrap()
+            .get_multiplexed_tokio_connection()
+            .await
+            .unwrap();
+        ::redis::cmd("FLUSHDB")
+            .query_async::<_, ()>(&mut conn)
+            .await
+            .unwrap();
+
+        let store = RedisStore::new(&cfg).await.unwrap();
+        store.prepare_queue(key()).await.unwrap();
+        Arc::new(store)
+    }
+
+    async fn check_commit_events(store: Arc<dyn EventStore>) {
+        assert_eq!(store.get_history_point().await.unwrap(), None);
+
+        let queued = store.commit_events(entries(1..=3), 4).await.unwrap();
+        assert_eq!(queued, 3);
+        assert_eq!(store.get_history_point().await.unwrap(), Some(4));
+
+        // The events below the stored history point are already queued
+        let queued = store.commit_events(entries(2..=5), 6).await.unwrap();
+        assert_eq!(queued, 2);
+        assert_eq!(store.get_history_point().await.unwrap(), Some(6));
+
+        let queued = store.commit_events(entries(4..=5), 6).await.unwrap();
+        assert_eq!(queued, 0);
+
+        assert_eq!(store.queue_len(key()).await.unwrap(), 5);
+
+        let events = store.get_events(key()).await.unwrap();
+        assert_eq!(history_points(&events), vec![1, 2, 3, 4, 5]);
+    }
+
+    async fn check_commit_without_events(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=2), 3).await.unwrap();
+
+        // An empty batch still moves the history point, e.g. when the events are of unknown kind
+        let queued = store.commit_events(vec![], 10).await.unwrap();
+        assert_eq!(queued, 0);
+        assert_eq!(store.get_history_point().await.unwrap(), Some(10));
+        assert_eq!(store.queue_len(key()).await.unwrap(), 2);
+    }
+
+    async fn check_claim_timeout(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=2), 3).await.unwrap();
+
+        let events = store.get_events(key()).await.unwrap();
+        assert_eq!(history_points(&events), vec![1, 2]);
+
+        // Delivered events are not returned again until the claim timeout passes
+        assert!(store.get_events(key()).await.unwrap().is_empty());
+
+        store.ack_event(key(), &events[0].id).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(CLAIM_TIMEOUT * 2)).await;
+
+        let reclaimed = store.get_events(key()).await.unwrap();
+        assert_eq!(history_points(&reclaimed), vec![2]);
+        assert_eq!(reclaimed[0].id, events[1].id);
+        assert_eq!(store.queue_len(key()).await.unwrap(), 1);
+    }
+
+    async fn check_pending_events(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=3), 4).await.unwrap();
+        assert!(store.get_pending_events(key()).await.unwrap().is_empty());
+
+        let events = store.get_events(key()).await.unwrap();
+        store.ack_event(key(), &events[0].id).await.unwrap();
+        store.commit_events(entries(4..=4), 5).await.unwrap();
+
+        // Only the claimed events are pending, and reading them doesn't claim them again
+        let pending = store.get_pending_events(key()).await.unwrap();
+        assert_eq!(history_points(&pending), vec![2, 3]);
+        assert_eq!(pending[0].id, events[1].id);
+        assert_eq!(
+            history_points(&store.get_events(key()).await.unwrap()),
+            vec![4]
+        );
+    }
+
+    async fn check_is_queued(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=3), 4).await.unwrap();
+        let mut events = store.get_events(key()).await.unwrap();
+
+        store.ack_event(key(), &events[0].id).await.unwrap();
+        let (id, dead) = (events[2].id.clone(), events.remove(1));
+        store
+            .dead_letter(key(), dead.clone(), 1, vec![])
+            .await
+            .unwrap();
+
+        assert!(!store.is_queued(key(), &events[0].id).await.unwrap());
+        assert!(!store.is_queued(key(), &dead.id).await.unwrap());
+        assert!(store.is_queued(key(), &id).await.unwrap());
+    }
+
+    async fn check_incr_attempts(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=2), 3).await.unwrap();
+        let events = store.get_events(key()).await.unwrap();
+
+        assert_eq!(store.incr_attempts(key(), &events[0].id).await.unwrap(), 1);
+        assert_eq!(store.incr_attempts(key(), &events[0].id).await.unwrap(), 2);
+        assert_eq!(store.incr_attempts(key(), &events[1].id).await.unwrap(), 1);
+    }
+
+    async fn check_media(store: Arc<dyn EventStore>) {
+        let url = "https://example.com/image.png";
+        assert_eq!(store.get_media(url).await.unwrap(), None);
+
+        store
+            .set_media(url, "mxc://example.com/first".to_owned())
+            .await
+            .unwrap();
+        store
+            .set_media(url, "mxc://example.com/second".to_owned())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.get_media(url).await.unwrap().as_deref(),
+            Some("mxc://example.com/second")
+        );
+    }
+
+    async fn check_dead_letter(store: Arc<dyn EventStore>) {
+        store.commit_events(entries(1..=2), 3).await.unwrap();
+        let mut events = store.get_events(key()).await.unwrap();
+
+        let errors = vec!["Failed to process".to_owned(), "Cause".to_owned()];
+        store
+            .dead_letter(key(), events.remove(0), 5, errors.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(store.queue_len(key()).await.unwrap(), 1);
+        assert_eq!(store.dead_letter_len(key()).await.unwrap(), 1);
+
+        let dead_letters = store.get_dead_letters(key(), 10).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].entry.0, 1);
+        assert_eq!(dead_letters[0].attempts, 5);
+        assert_eq!(dead_letters[0].errors, errors);
+
+        // The dead letter is not delivered again
+        tokio::time::sleep(Duration::from_millis(CLAIM_TIMEOUT * 2)).await;
+        let reclaimed = store.get_events(key()).await.unwrap();
+        assert_eq!(history_points(&reclaimed), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_memory_commit_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_events(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_commit_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_events(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_commit_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_events(redis(&dir, 1).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_commit_without_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_without_events(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_commit_without_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_without_events(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_commit_without_events() {
+        let dir = TempDir::new().unwrap();
+        check_commit_without_events(redis(&dir, 2).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_claim_timeout() {
+        let dir = TempDir::new().unwrap();
+        check_claim_timeout(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_claim_timeout() {
+        let dir = TempDir::new().unwrap();
+        check_claim_timeout(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_claim_timeout() {
+        let dir = TempDir::new().unwrap();
+        check_claim_timeout(redis(&dir, 3).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_pending_events() {
+        let dir = TempDir::new().unwrap();
+        check_pending_events(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_pending_events() {
+        let dir = TempDir::new().unwrap();
+        check_pending_events(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_pending_events() {
+        let dir = TempDir::new().unwrap();
+        check_pending_events(redis(&dir, 4).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_is_queued() {
+        let dir = TempDir::new().unwrap();
+        check_is_queued(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_is_queued() {
+        let dir = TempDir::new().unwrap();
+        check_is_queued(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_is_queued() {
+        let dir = TempDir::new().unwrap();
+        check_is_queued(redis(&dir, 8).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_incr_attempts() {
+        let dir = TempDir::new().unwrap();
+        check_incr_attempts(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_incr_attempts() {
+        let dir = TempDir::new().unwrap();
+        check_incr_attempts(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_incr_attempts() {
+        let dir = TempDir::new().unwrap();
+        check_incr_attempts(redis(&dir, 5).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_dead_letter() {
+        let dir = TempDir::new().unwrap();
+        check_dead_letter(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_dead_letter() {
+        let dir = TempDir::new().unwrap();
+        check_dead_letter(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_dead_letter() {
+        let dir = TempDir::new().unwrap();
+        check_dead_letter(redis(&dir, 6).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_media() {
+        let dir = TempDir::new().unwrap();
+        check_media(memory(&dir)).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_media() {
+        let dir = TempDir::new().unwrap();
+        check_media(sqlite(&dir).await).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn test_redis_media() {
+        let dir = TempDir::new().unwrap();
+        check_media(redis(&dir, 7).await).await;
+    }
+
+    /// The store is reopened, so the queue and the history point have to be in the database file.
+    #[tokio::test]
+    async fn test_sqlite_persists_events() {
+        let dir = TempDir::new().unwrap();
+        sqlite(&dir)
+            .await
+            .commit_events(entries(1..=2), 3)
+            .await
+            .unwrap();
+
+        let store = sqlite(&dir).await;
+        assert_eq!(store.get_history_point().await.unwrap(), Some(3));
+        assert_eq!(
+            history_points(&store.get_events(key()).await.unwrap()),
+            vec![1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_dead_letters_undecodable_events() {
+        let dir = TempDir::new().unwrap();
+        let store = sqlite(&dir).await;
+        store.commit_events(entries(1..=2), 3).await.unwrap();
+
+        rusqlite::Connection::open(dir.path().join("relayer.db"))
+            .unwrap()
+            .execute(
+                "INSERT INTO queue (queue, event) VALUES (?1, ?2)",
+                rusqlite::params![key().to_string(), b"garbage".to_vec()],
+            )
+            .unwrap();
+
+        assert_eq!(
+            history_points(&store.get_events(key()).await.unwrap()),
+            vec![1, 2]
+        );
+        assert_eq!(store.queue_len(key()).await.unwrap(), 2);
+        assert_eq!(store.dead_letter_len(key()).await.unwrap(), 1);
+
+        // It is left in the dead letter queue for the manual inspection
+        assert!(store.get_dead_letters(key(), 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+use async_trait::async_trait;
+use candid::{Decode, Encode};
+use eyre::Context as _;
+use proxy_types::models::history_event::HistoryEventEntry;
+use redis::{
+    aio::MultiplexedConnection,
+    streams::{StreamClaimReply, StreamId, StreamRangeReply, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Script,
+};
+
+use super::{DeadLetter, EventStore, QueuedEvent};
+use crate::{
+    config::Config,
+    consts::{GROUP_SPACES_KEY, HISTORY_POINT_KEY, MATRIX_SESSIONS_KEY, MEDIA_KEY},
+    consumer::QueueKey,
+    utils::trace::TraceContext,
+};
+
+static EVENT_FIELD: &str = "event";
+static ERROR_FIELD: &str = "error";
+static ATTEMPTS_FIELD: &str = "attempts";
+static TRACE_FIELD: &str = "trace";
+
+// KEYS[1] is the history point key, KEYS[2..] are the queues of the events.
+// ARGV[1] is the new history point, ARGV[2..] are triples of the event history point, payload and
+// trace context.
+static COMMIT_SCRIPT: &str = r#"
+local current = tonumber(redis.call('GET', KEYS[1]) or '0')
+local queued = 0
+
+for i = 2, #KEYS do
+    local j = 3 * i - 4
+    local point = tonumber(ARGV[j])
+    if point >= current then
+        redis.call('XADD', KEYS[i], '*', 'event', ARGV[j + 1], 'trace', ARGV[j + 2])
+        queued = queued + 1
+    end
+end
+
+redis.call('SET', KEYS[1], ARGV[1])
+return queued
+"#;
+
+/// Queues are Redis Streams read through a consumer group, so several relayer replicas can share
+/// them.
+pub struct RedisStore {
+    conn: MultiplexedConnection,
+    commit_script: Script,
+    consumer_group: String,
+    consumer_name: String,
+    claim_timeout: u64,
+    limit: u64,
+}
+
+impl RedisStore {
+    pub async fn new(cfg: &Config) -> eyre::Result<Self> {
+        let conn = redis::Client::open(cfg.redis_url.clone())
+            .wrap_err("Failed to establish connection with redis")?
+            .get_multiplexed_tokio_connection()
+            .await
+            .wrap_err("Failed to get redis connection")?;
+
+        Ok(Self {
+            conn,
+            commit_script: Script::new(COMMIT_SCRIPT),
+            consumer_group: cfg.consumer_group.clone(),
+            consumer_name: cfg.consumer_name.clone(),
+            claim_timeout: cfg.claim_timeout,
+            limit: cfg.limit,
+        })
+    }
+
+    async fn claim_stale_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let mut conn = self.conn.clone();
+
+        // Reply is [next_start_id, claimed_entries, deleted_ids], the last one only since redis 7
+        let reply: Vec<redis::Value> = redis::cmd("XAUTOCLAIM")
+            .arg(key.to_string())
+            .arg(&self.consumer_group)
+            .arg(&self.consumer_name)
+            .arg(self.claim_timeout)
+            .arg("0-0")
+            .arg("COUNT")
+            .arg(self.limit)
+            .query_async(&mut conn)
+            .await
+            .wrap_err_with(|| format!("Failed to claim stale events from the \"{key}\" queue"))?;
+
+        let claimed: StreamClaimReply = match reply.get(1) {
+            Some(value) => redis::from_redis_value(value).wrap_err_with(|| {
+                format!("Failed to parse claimed events from the \"{key}\" queue")
+            })?,
+            None => StreamClaimReply::default(),
+        };
+
+        if !claimed.ids.is_empty() {
+            tracing::info!(
+                queue = key.to_string(),
+                "Claimed {} stale event(s)",
+                claimed.ids.len()
+            );
+        }
+
+        let mut events = vec![];
+
+        for id in claimed.ids {
+            events.extend(self.decode_or_dead_letter(&key, id).await?);
+        }
+
+        Ok(events)
+    }
+
+    /// Decodes the stream entry. The entry which can't be decoded is moved to the dead letter queue
+    /// as is, otherwise it would fail each poll of the queue and never reach the attempts limit.
+    async fn decode_or_dead_letter(
+        &self,
+        key: &QueueKey,
+        id: StreamId,
+    ) -> eyre::Result<Option<QueuedEvent>> {
+        let err = match decode_event(key, id.clone()) {
+            Ok(event) => return Ok(Some(event)),
+            Err(err) => err,
+        };
+
+        tracing::error!(
+            queue = key.to_string(),
+            id = id.id,
+            error = format!("{err:#}"),
+            "Failed to decode event, moving it to the \"{}\" queue",
+            key.dead_letter()
+        );
+
+        let bytea: Vec<u8> = id.get(EVENT_FIELD).unwrap_or_default();
+        let errors: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+        let errors = serde_json::to_vec(&errors).wrap_err("Failed to serialize error chain")?;
+
+        let fields = [
+            (EVENT_FIELD, bytea),
+            (ERROR_FIELD, errors),
+            (ATTEMPTS_FIELD, b"0".to_vec()),
+        ];
+
+        self.move_to_dead_letter(key, &id.id, &fields).await?;
+        Ok(None)
+    }
+
+    /// Adds the entry to the dead letter queue and acknowledges it in the original queue atomically.
+    async fn move_to_dead_letter(
+        &self,
+        key: &QueueKey,
+        id: &str,
+        fields: &[(&str, Vec<u8>)],
+    ) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        redis::pipe()
+            .atomic()
+            .xadd(key.dead_letter(), "*", fields)
+            .xack(key.to_string(), &self.consumer_group, &[id])
+            .xdel(key.to_string(), &[id])
+            .hdel(key.attempts(), id)
+            .query_async(&mut conn)
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to move event \"{id}\" to the \"{}\" queue",
+                    key.dead_letter()
+                )
+            })
+    }
+}
+
+#[async_trait]
+impl EventStore for RedisStore {
+    async fn get_history_point(&self) -> eyre::Result<Option<u64>> {
+        let mut conn = self.conn.clone();
+
+        conn.get(HISTORY_POINT_KEY)
+            .await
+            .wrap_err("Failed to get history point")
+    }
+
+    async fn set_history_point(&self, point: u64) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        conn.set(HISTORY_POINT_KEY, point)
+            .await
+            .wrap_err("Failed to set history point")
+    }
+
+    async fn commit_events(
+        &self,
+        events: Vec<(QueueKey, HistoryEventEntry, TraceContext)>,
+        history_point: u64,
+    ) -> eyre::Result<usize> {
+        let mut conn = self.conn.clone();
+
+        let mut invocation = self.commit_script.prepare_invoke();
+        invocation.key(HISTORY_POINT_KEY).arg(history_point);
+
+        for (key, event, trace) in events {
+            let bytea = Encode!(&event).wrap_err_with(|| {
+                format!(
+                    "Failed to encode event: {:?} before queueing to the \"{key}\" queue",
+                    event
+                )
+            })?;
+
+            let trace = serde_json::to_vec(&trace).wrap_err("Failed to serialize trace context")?;
+
+            invocation
+                .key(key.to_string())
+                .arg(event.0)
+                .arg(bytea)
+                .arg(trace);
+        }
+
+        invocation.invoke_async(&mut conn).await.wrap_err_with(|| {
+            format!("Failed to commit events up to the history point {history_point}")
+        })
+    }
+
+    async fn prepare_queue(&self, key: QueueKey) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+        let group = &self.consumer_group;
+
+        let res: redis::RedisResult<()> = conn
+            .xgroup_create_mkstream(key.to_string(), group, "0")
+            .await;
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) if e.code() == Some("BUSYGROUP") => Ok(()),
+            Err(e) => Err(e).wrap_err_with(|| {
+                format!("Failed to create consumer group \"{group}\" for the \"{key}\" queue")
+            }),
+        }
+    }
+
+    async fn get_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let mut conn = self.conn.clone();
+
+        let mut events = self.claim_stale_events(key.clone()).await?;
+
+        let count = self.limit.saturating_sub(events.len() as u64);
+        if count == 0 {
+            return Ok(events);
+        }
+
+        let opts = StreamReadOptions::default()
+            .group(&self.consumer_group, &self.consumer_name)
+            .count(count as usize);
+
+        let reply: StreamReadReply = conn
+            .xread_options(&[key.to_string()], &[">"], &opts)
+            .await
+            .wrap_err_with(|| format!("Failed to get events from the \"{key}\" queue"))?;
+
+        for stream in reply.keys {
+            for id in stream.ids {
+                events.extend(self.decode_or_dead_letter(&key, id).await?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn get_pending_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let mut conn = self.conn.clone();
+
+        // Reading from the `0` id returns the pending entries of the consumer instead of the new ones
+        let opts = StreamReadOptions::default().group(&self.consumer_group, &self.consumer_name);
+
+        let reply: StreamReadReply = conn
+            .xread_options(&[key.to_string()], &["0"], &opts)
+            .await
+            .wrap_err_with(|| format!("Failed to get pending events from the \"{key}\" queue"))?;
+
+        let mut events = vec![];
+
+        for stream in reply.keys {
+            for id in stream.ids {
+                events.extend(self.decode_or_dead_letter(&key, id).await?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn is_queued(&self, key: QueueKey, id: &str) -> eyre::Result<bool> {
+        let mut conn = self.conn.clone();
+
+        // The acknowledged and the dead-lettered events are deleted from the stream
+        let reply: StreamRangeReply = conn
+            .xrange_count(key.to_string(), id, id, 1)
+            .await
+            .wrap_err_with(|| format!("Failed to check event \"{id}\" in the \"{key}\" queue"))?;
+
+        Ok(!reply.ids.is_empty())
+    }
+
+    async fn ack_event(&self, key: QueueKey, id: &str) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        redis::pipe()
+            .atomic()
+            .xack(key.to_string(), &self.consumer_group, &[id])
+            .xdel(key.to_string(), &[id])
+            .hdel(key.attempts(), id)
+            .query_async(&mut conn)
+            .await
+            .wrap_err_with(|| format!("Failed to ack event \"{id}\" in the \"{key}\" queue"))
+    }
+
+    async fn incr_attempts(&self, key: QueueKey, id: &str) -> eyre::Result<u64> {
+        let mut conn = self.conn.clone();
+
+        conn.hincr(key.attempts(), id, 1)
+            .await
+            .wrap_err_with(|| format!("Failed to increment attempts of the event \"{id}\""))
+    }
+
+    async fn dead_letter(
+        &self,
+        key: QueueKey,
+        event: QueuedEvent,
+        attempts: u64,
+        errors: Vec<String>,
+    ) -> eyre::Result<()> {
+        let bytea = Encode!(&event.entry).wrap_err_with(|| {
+            format!(
+                "Failed to encode event: {:?} before moving to the \"{}\" queue",
+                event.entry,
+                key.dead_letter()
+            )
+        })?;
+
+        let errors = serde_json::to_vec(&errors).wrap_err("Failed to serialize error chain")?;
+
+        let fields = [
+            (EVENT_FIELD, bytea),
+            (ERROR_FIELD, errors),
+            (ATTEMPTS_FIELD, attempts.to_string().into_bytes()),
+        ];
+
+        self.move_to_dead_letter(&key, &event.id, &fields).await
+    }
+
+    async fn get_group_space(&self, group_id: u64) -> eyre::Result<Option<String>> {
+        let mut conn = self.conn.clone();
+
+        conn.hget(GROUP_SPACES_KEY, group_id)
+            .await
+            .wrap_err_with(|| format!("Failed to get space of the group {group_id}"))
+    }
+
+    async fn set_group_space(&self, group_id: u64, space_id: String) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        conn.hset(GROUP_SPACES_KEY, group_id, space_id)
+            .await
+            .wrap_err_with(|| format!("Failed to set space of the group {group_id}"))
+    }
+
+    async fn get_media(&self, url: &str) -> eyre::Result<Option<String>> {
+        let mut conn = self.conn.clone();
+
+        conn.hget(MEDIA_KEY, url)
+            .await
+            .wrap_err_with(|| format!("Failed to get media of the url \"{url}\""))
+    }
+
+    async fn set_media(&self, url: &str, mxc: String) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        conn.hset(MEDIA_KEY, url, mxc)
+            .await
+            .wrap_err_with(|| format!("Failed to set media of the url \"{url}\""))
+    }
+
+    async fn get_matrix_session(&self, replica: &str) -> eyre::Result<Option<String>> {
+        let mut conn = self.conn.clone();
+
+        conn.hget(MATRIX_SESSIONS_KEY, replica)
+            .await
+            .wrap_err_with(|| format!("Failed to get matrix session of the replica \"{replica}\""))
+    }
+
+    async fn set_matrix_session(&self, replica: &str, session: String) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        conn.hset(MATRIX_SESSIONS_KEY, replica, session)
+            .await
+            .wrap_err_with(|| format!("Failed to set matrix session of the replica \"{replica}\""))
+    }
+
+    async fn queue_event(
+        &self,
+        key: QueueKey,
+        event: HistoryEventEntry,
+        trace: TraceContext,
+    ) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        let bytea = Encode!(&event).wrap_err_with(|| {
+            format!(
+                "Failed to encode event: {:?} before queueing to the \"{key}\" queue",
+                event
+            )
+        })?;
+
+        let trace = serde_json::to_vec(&trace).wrap_err("Failed to serialize trace context")?;
+
+        let _: String = conn
+            .xadd(
+                key.to_string(),
+                "*",
+                &[(EVENT_FIELD, bytea), (TRACE_FIELD, trace)],
+            )
+            .await
+            .wrap_err_with(|| {
+                format!("Failed to queue event: {:?} to the \"{key}\" queue", event)
+            })?;
+
+        Ok(())
+    }
+
+    async fn queue_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        let mut conn = self.conn.clone();
+
+        conn.xlen(key.to_string())
+            .await
+            .wrap_err_with(|| format!("Failed to get length of the \"{key}\" queue"))
+    }
+
+    async fn dead_letter_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        let mut conn = self.conn.clone();
+
+        conn.xlen(key.dead_letter()).await.wrap_err_with(|| {
+            format!(
+                "Failed to get length of the \"{}\" queue",
+                key.dead_letter()
+            )
+        })
+    }
+
+    async fn get_dead_letters(&self, key: QueueKey, limit: u64) -> eyre::Result<Vec<DeadLetter>> {
+        let mut conn = self.conn.clone();
+
+        let reply: StreamRangeReply = conn
+            .xrange_count(key.dead_letter(), "-", "+", limit)
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to get events from the \"{}\" queue",
+                    key.dead_letter()
+                )
+            })?;
+
+        // The entries moved there because they can't be decoded are left for the manual inspection
+        let dead_letters = reply
+            .ids
+            .into_iter()
+            .filter_map(|id| {
+                let entry_id = id.id.clone();
+
+                decode_dead_letter(&key, id)
+                    .map_err(|e| {
+                        tracing::warn!(
+                            queue = key.dead_letter(),
+                            id = entry_id,
+                            error = format!("{e:#}"),
+                            "Skipping dead letter which can't be decoded"
+                        )
+                    })
+                    .ok()
+            })
+            .collect();
+
+        Ok(dead_letters)
+    }
+
+    async fn ping(&self) -> eyre::Result<()> {
+        let mut conn = self.conn.clone();
+
+        let _: String = redis::cmd("PING")
+            .query_async(&mut conn)
+            .await
+            .wrap_err("Failed to ping redis")?;
+
+        Ok(())
+    }
+}
+
+fn decode_event(key: &QueueKey, id: StreamId) -> eyre::Result<QueuedEvent> {
+    let bytea: Vec<u8> = id.get(EVENT_FIELD).ok_or_else(|| {
+        eyre::eyre!(
+            "Event \"{}\" in the \"{key}\" queue has no \"{EVENT_FIELD}\" field",
+            id.id
+        )
+    })?;
+
+    let entry = Decode!(&bytea, HistoryEventEntry)
+        .wrap_err_with(|| format!("Failed to decode event from the \"{key}\" queue"))?;
+
+    // Events queued before the trace context was introduced have no such field, the broken trace
+    // context only breaks the trace, so the event is still processed
+    let trace = id
+        .get::<Vec<u8>>(TRACE_FIELD)
+        .and_then(|trace| {
+            serde_json::from_slice(&trace)
+                .map_err(|e| {
+                    tracing::warn!(
+                        id = id.id,
+                        error = e.to_string(),
+                        "Failed to parse trace context of the event"
+                    )
+                })
+                .ok()
+        })
+        .unwrap_or_default();
+
+    Ok(QueuedEvent {
+        id: id.id,
+        entry,
+        trace,
+    })
+}
+
+fn decode_dead_letter(key: &QueueKey, id: StreamId) -> eyre::Result<DeadLetter> {
+    let dead_letter = key.dead_letter();
+
+    let errors: Vec<u8> = id.get(ERROR_FIELD).unwrap_or_else(|| b"[]".to_vec());
+    let errors = serde_json::from_slice(&errors).wrap_err_with(|| {
+        format!(
+            "Failed to parse error chain of the event \"{}\" in the \"{dead_letter}\" queue",
+            id.id
+        )
+    })?;
+
+    let attempts: u64 = id.get(ATTEMPTS_FIELD).unwrap_or_default();
+    let QueuedEvent { id, entry, .. } = decode_event(key, id)?;
+
+    Ok(DeadLetter {
+        id,
+        entry,
+        attempts,
+        errors,
+    })
+}
@@ -0,0 +1,699 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use candid::{Decode, Encode};
+use eyre::Context as _;
+use proxy_types::models::history_event::HistoryEventEntry;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use super::{DeadLetter, EventStore, QueuedEvent};
+use crate::{
+    config::Config, consts::HISTORY_POINT_KEY, consumer::QueueKey, utils::trace::TraceContext,
+};
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS kv (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS queue (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        queue TEXT NOT NULL,
+        event BLOB NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        claimed_at INTEGER,
+        trace TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS queue_queue_id ON queue (queue, id);
+
+    CREATE TABLE IF NOT EXISTS dead_letters (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        queue TEXT NOT NULL,
+        event BLOB NOT NULL,
+        attempts INTEGER NOT NULL,
+        errors TEXT NOT NULL,
+        failed_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS group_spaces (
+        group_id INTEGER PRIMARY KEY,
+        space_id TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS matrix_sessions (
+        replica TEXT PRIMARY KEY,
+        session TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS media (
+        url TEXT PRIMARY KEY,
+        mxc TEXT NOT NULL
+    );
+";
+
+/// Embedded store for single node deployments without Redis.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    claim_timeout: u64,
+    limit: u64,
+}
+
+impl SqliteStore {
+    pub async fn new(cfg: &Config) -> eyre::Result<Self> {
+        let path = cfg.sqlite_path.clone();
+
+        let conn = tokio::task::spawn_blocking(move || -> eyre::Result<Connection> {
+            let conn = Connection::open(&path)
+                .wrap_err_with(|| format!("Failed to open sqlite database at \"{path}\""))?;
+
+            conn.execute_batch(SCHEMA)
+                .wrap_err("Failed to create sqlite schema")?;
+
+            migrate_trace_column(&conn)?;
+
+            Ok(conn)
+        })
+        .await
+        .wrap_err("Sqlite task panicked")??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            claim_timeout: cfg.claim_timeout,
+            limit: cfg.limit,
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> eyre::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> eyre::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| eyre::eyre!("Sqlite connection mutex is poisoned"))?;
+            f(&mut conn)
+        })
+        .await
+        .wrap_err("Sqlite task panicked")?
+    }
+}
+
+#[async_trait]
+impl EventStore for SqliteStore {
+    async fn get_history_point(&self) -> eyre::Result<Option<u64>> {
+        self.with_conn(|conn| {
+            let point: Option<String> = conn
+                .query_row(
+                    "SELECT value FROM kv WHERE key = ?1",
+                    [HISTORY_POINT_KEY],
+                    |row| row.get(0),
+                )
+                .optional()
+                .wrap_err("Failed to get history point")?;
+
+            point
+                .map(|p| p.parse().wrap_err("Failed to parse history point"))
+                .transpose()
+        })
+        .await
+    }
+
+    async fn set_history_point(&self, point: u64) -> eyre::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![HISTORY_POINT_KEY, point.to_string()],
+            )
+            .wrap_err("Failed to set history point")?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn commit_events(
+        &self,
+        events: Vec<(QueueKey, HistoryEventEntry, TraceContext)>,
+        history_point: u64,
+    ) -> eyre::Result<usize> {
+        let events = events
+            .into_iter()
+            .map(|(key, event, trace)| {
+                let bytea = Encode!(&event).wrap_err_with(|| {
+                    format!(
+                        "Failed to encode event: {:?} before queueing to the \"{key}\" queue",
+                        event
+                    )
+                })?;
+                let trace =
+                    serde_json::to_string(&trace).wrap_err("Failed to serialize trace context")?;
+                Ok((key, event.0, bytea, trace))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        self.with_conn(move |conn| {
+            let tx = conn
+                .transaction()
+                .wrap_err("Failed to start sqlite transaction")?;
+
+            let current: u64 = tx
+                .query_row(
+                    "SELECT value FROM kv WHERE key = ?1",
+                    [HISTORY_POINT_KEY],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()
+                .wrap_err("Failed to get history point")?
+                .map(|p| p.parse().wrap_err("Failed to parse history point"))
+                .transpose()?
+                .unwrap_or_default();
+
+            let mut queued = 0;
+
+            for (key, point, bytea, trace) in events {
+                if point < current {
+                    continue;
+                }
+
+                tx.execute(
+                    "INSERT INTO queue (queue, event, trace) VALUES (?1, ?2, ?3)",
+                    params![key.to_string(), bytea, trace],
+                )
+                .wrap_err_with(|| format!("Failed to queue event to the \"{key}\" queue"))?;
+
+                queued += 1;
+            }
+
+            tx.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![HISTORY_POINT_KEY, history_point.to_string()],
+            )
+            .wrap_err("Failed to set history point")?;
+
+            tx.commit()
+                .wrap_err("Failed to commit sqlite transaction")?;
+
+            Ok(queued)
+        })
+        .await
+    }
+
+    async fn prepare_queue(&self, _key: QueueKey) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn get_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        let limit = self.limit;
+        let now = now_millis();
+        let stale_before = now.saturating_sub(self.claim_timeout);
+
+        self.with_conn(move |conn| {
+            let tx = conn
+                .transaction()
+                .wrap_err("Failed to start sqlite transaction")?;
+
+            let rows = {
+                let mut stmt = tx
+                    .prepare(
+                        "SELECT id, event, trace FROM queue
+                         WHERE queue = ?1 AND (claimed_at IS NULL OR claimed_at <= ?2)
+                         ORDER BY id LIMIT ?3",
+                    )
+                    .wrap_err("Failed to prepare events query")?;
+
+                let rows = stmt
+                    .query_map(params![key.to_string(), stale_before, limit], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, Vec<u8>>(1)?,
+                            row.get::<_, Option<String>>(2)?,
+                        ))
+                    })
+                    .wrap_err_with(|| format!("Failed to get events from the \"{key}\" queue"))?;
+
+                rows.collect::<Result<Vec<_>, _>>()
+                    .wrap_err_with(|| format!("Failed to read events from the \"{key}\" queue"))?
+            };
+
+            let mut events = Vec::with_capacity(rows.len());
+
+            for (id, bytea, trace) in rows {
+                let entry = match Decode!(&bytea, HistoryEventEntry) {
+                    Ok(entry) => entry,
+                    // Otherwise it would fail each poll of the queue and never reach the attempts
+                    // limit, so it's moved to the dead letter queue as is
+                    Err(e) => {
+                        let err = eyre::Report::new(e)
+                            .wrap_err(format!("Failed to decode event from the \"{key}\" queue"));
+
+                        tracing::error!(
+                            queue = key.to_string(),
+                            id,
+                            error = format!("{err:#}"),
+                            "Failed to decode event, moving it to the \"{}\" queue",
+                            key.dead_letter()
+                        );
+
+                        let errors: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+                        move_to_dead_letter(&tx, &key, id, &bytea, 0, &errors)?;
+                        continue;
+                    }
+                };
+
+                tx.execute(
+                    "UPDATE queue SET claimed_at = ?1 WHERE id = ?2",
+                    params![now, id],
+                )
+                .wrap_err_with(|| format!("Failed to claim event \"{id}\""))?;
+
+                events.push(QueuedEvent {
+                    id: id.to_string(),
+                    entry,
+                    trace: parse_trace(id, trace),
+                });
+            }
+
+            tx.commit()
+                .wrap_err("Failed to commit sqlite transaction")?;
+
+            Ok(events)
+        })
+        .await
+    }
+
+    async fn get_pending_events(&self, key: QueueKey) -> eyre::Result<Vec<QueuedEvent>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, event, trace FROM queue
+                     WHERE queue = ?1 AND claimed_at IS NOT NULL
+                     ORDER BY id",
+                )
+                .wrap_err("Failed to prepare pending events query")?;
+
+            let rows = stmt
+                .query_map([key.to_string()], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                    ))
+                })
+                .wrap_err_with(|| {
+                    format!("Failed to get pending events from the \"{key}\" queue")
+                })?;
+
+            let mut events = vec![];
+
+            // The undecodable events are moved to the dead letter queue when they are claimed again
+            for row in rows {
+                let (id, bytea, trace) = row.wrap_err_with(|| {
+                    format!("Failed to read pending events from the \"{key}\" queue")
+                })?;
+
+                let Ok(entry) = Decode!(&bytea, HistoryEventEntry) else {
+                    continue;
+                };
+
+                events.push(QueuedEvent {
+                    id: id.to_string(),
+                    entry,
+                    trace: parse_trace(id, trace),
+                });
+            }
+
+            Ok(events)
+        })
+        .await
+    }
+
+    async fn is_queued(&self, key: QueueKey, id: &str) -> eyre::Result<bool> {
+        let id = parse_id(id)?;
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM queue WHERE id = ?1)",
+                [id],
+                |row| row.get(0),
+            )
+            .wrap_err_with(|| format!("Failed to check event \"{id}\" in the \"{key}\" queue"))
+        })
+        .await
+    }
+
+    async fn ack_event(&self, key: QueueKey, id: &str) -> eyre::Result<()> {
+        let id = parse_id(id)?;
+
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM queue WHERE id = ?1", [id])
+                .wrap_err_with(|| format!("Failed to ack event \"{id}\" in the \"{key}\" queue"))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn incr_attempts(&self, _key: QueueKey, id: &str) -> eyre::Result<u64> {
+        let id = parse_id(id)?;
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "UPDATE queue SET attempts = attempts + 1 WHERE id = ?1 RETURNING attempts",
+                [id],
+                |row| row.get(0),
+            )
+            .wrap_err_with(|| format!("Failed to increment attempts of the event \"{id}\""))
+        })
+        .await
+    }
+
+    async fn dead_letter(
+        &self,
+        key: QueueKey,
+        event: QueuedEvent,
+        attempts: u64,
+        errors: Vec<String>,
+    ) -> eyre::Result<()> {
+        let id = parse_id(&event.id)?;
+
+        let bytea = Encode!(&event.entry).wrap_err_with(|| {
+            format!(
+                "Failed to encode event: {:?} before moving to the \"{}\" queue",
+                event.entry,
+                key.dead_letter()
+            )
+        })?;
+
+        self.with_conn(move |conn| {
+            let tx = conn
+                .transaction()
+                .wrap_err("Failed to start sqlite transaction")?;
+
+            move_to_dead_letter(&tx, &key, id, &bytea, attempts, &errors)?;
+
+            tx.commit().wrap_err("Failed to commit sqlite transaction")
+        })
+        .await
+    }
+
+    async fn get_group_space(&self, group_id: u64) -> eyre::Result<Option<String>> {
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT space_id FROM group_spaces WHERE group_id = ?1",
+                [group_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .wrap_err_with(|| format!("Failed to get space of the group {group_id}"))
+        })
+        .await
+    }
+
+    async fn set_group_space(&self, group_id: u64, space_id: String) -> eyre::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO group_spaces (group_id, space_id) VALUES (?1, ?2)
+                 ON CONFLICT (group_id) DO UPDATE SET space_id = excluded.space_id",
+                params![group_id, space_id],
+            )
+            .wrap_err_with(|| format!("Failed to set space of the group {group_id}"))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_media(&self, url: &str) -> eyre::Result<Option<String>> {
+        let url = url.to_owned();
+
+        self.with_conn(move |conn| {
+            conn.query_row("SELECT mxc FROM media WHERE url = ?1", [&url], |row| {
+                row.get(0)
+            })
+            .optional()
+            .wrap_err_with(|| format!("Failed to get media of the url \"{url}\""))
+        })
+        .await
+    }
+
+    async fn set_media(&self, url: &str, mxc: String) -> eyre::Result<()> {
+        let url = url.to_owned();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO media (url, mxc) VALUES (?1, ?2)
+                 ON CONFLICT (url) DO UPDATE SET mxc = excluded.mxc",
+                params![url, mxc],
+            )
+            .wrap_err_with(|| format!("Failed to set media of the url \"{url}\""))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_matrix_session(&self, replica: &str) -> eyre::Result<Option<String>> {
+        let replica = replica.to_owned();
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT session FROM matrix_sessions WHERE replica = ?1",
+                [&replica],
+                |row| row.get(0),
+            )
+            .optional()
+            .wrap_err_with(|| format!("Failed to get matrix session of the replica \"{replica}\""))
+        })
+        .await
+    }
+
+    async fn set_matrix_session(&self, replica: &str, session: String) -> eyre::Result<()> {
+        let replica = replica.to_owned();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO matrix_sessions (replica, session) VALUES (?1, ?2)
+                 ON CONFLICT (replica) DO UPDATE SET session = excluded.session",
+                params![replica, session],
+            )
+            .wrap_err_with(|| {
+                format!("Failed to set matrix session of the replica \"{replica}\"")
+            })?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn queue_event(
+        &self,
+        key: QueueKey,
+        event: HistoryEventEntry,
+        trace: TraceContext,
+    ) -> eyre::Result<()> {
+        let bytea = Encode!(&event).wrap_err_with(|| {
+            format!(
+                "Failed to encode event: {:?} before queueing to the \"{key}\" queue",
+                event
+            )
+        })?;
+
+        let trace = serde_json::to_string(&trace).wrap_err("Failed to serialize trace context")?;
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO queue (queue, event, trace) VALUES (?1, ?2, ?3)",
+                params![key.to_string(), bytea, trace],
+            )
+            .wrap_err_with(|| format!("Failed to queue event to the \"{key}\" queue"))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn queue_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM queue WHERE queue = ?1",
+                [key.to_string()],
+                |row| row.get(0),
+            )
+            .wrap_err_with(|| format!("Failed to get length of the \"{key}\" queue"))
+        })
+        .await
+    }
+
+    async fn dead_letter_len(&self, key: QueueKey) -> eyre::Result<u64> {
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM dead_letters WHERE queue = ?1",
+                [key.to_string()],
+                |row| row.get(0),
+            )
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to get length of the \"{}\" queue",
+                    key.dead_letter()
+                )
+            })
+        })
+        .await
+    }
+
+    async fn get_dead_letters(&self, key: QueueKey, limit: u64) -> eyre::Result<Vec<DeadLetter>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, event, attempts, errors FROM dead_letters
+                     WHERE queue = ?1 ORDER BY id LIMIT ?2",
+                )
+                .wrap_err("Failed to prepare dead letters query")?;
+
+            let rows = stmt
+                .query_map(params![key.to_string(), limit], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, u64>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                })
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to get events from the \"{}\" queue",
+                        key.dead_letter()
+                    )
+                })?
+                .collect::<Result<Vec<_>, _>>()
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to read events from the \"{}\" queue",
+                        key.dead_letter()
+                    )
+                })?;
+
+            let mut dead_letters = Vec::with_capacity(rows.len());
+
+            for (id, bytea, attempts, errors) in rows {
+                // The events moved there because they can't be decoded are left for the manual
+                // inspection
+                let entry = match Decode!(&bytea, HistoryEventEntry) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        tracing::warn!(
+                            queue = key.dead_letter(),
+                            id,
+                            error = e.to_string(),
+                            "Skipping dead letter which can't be decoded"
+                        );
+                        continue;
+                    }
+                };
+
+                let errors = serde_json::from_str(&errors).wrap_err_with(|| {
+                    format!("Failed to parse error chain of the dead letter \"{id}\"")
+                })?;
+
+                dead_letters.push(DeadLetter {
+                    id: id.to_string(),
+                    entry,
+                    attempts,
+                    errors,
+                });
+            }
+
+            Ok(dead_letters)
+        })
+        .await
+    }
+
+    async fn ping(&self) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT 1", [], |_| Ok(()))
+                .wrap_err("Failed to query sqlite")
+        })
+        .await
+    }
+}
+
+/// The broken trace context only breaks the trace, so the event is still processed.
+fn parse_trace(id: i64, trace: Option<String>) -> TraceContext {
+    trace
+        .and_then(|trace| {
+            serde_json::from_str(&trace)
+                .map_err(|e| {
+                    tracing::warn!(
+                        id,
+                        error = e.to_string(),
+                        "Failed to parse trace context of the event"
+                    )
+                })
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Adds the event to the dead letter queue and removes it from the queue, within the transaction.
+fn move_to_dead_letter(
+    tx: &Transaction,
+    key: &QueueKey,
+    id: i64,
+    bytea: &[u8],
+    attempts: u64,
+    errors: &[String],
+) -> eyre::Result<()> {
+    let errors = serde_json::to_string(errors).wrap_err("Failed to serialize error chain")?;
+
+    tx.execute(
+        "INSERT INTO dead_letters (queue, event, attempts, errors, failed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![key.to_string(), bytea, attempts, errors, now_millis()],
+    )
+    .wrap_err_with(|| format!("Failed to move event \"{id}\" to the dead letter queue"))?;
+
+    tx.execute("DELETE FROM queue WHERE id = ?1", [id])
+        .wrap_err_with(|| format!("Failed to ack event \"{id}\" in the \"{key}\" queue"))?;
+
+    Ok(())
+}
+
+/// Adds the `trace` column to the queue table created before the trace context was introduced.
+fn migrate_trace_column(conn: &Connection) -> eyre::Result<()> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('queue') WHERE name = 'trace'",
+            [],
+            |row| row.get(0),
+        )
+        .wrap_err("Failed to check the trace column of the queue table")?;
+
+    if !exists {
+        conn.execute("ALTER TABLE queue ADD COLUMN trace TEXT", [])
+            .wrap_err("Failed to add the trace column to the queue table")?;
+    }
+
+    Ok(())
+}
+
+fn parse_id(id: &str) -> eyre::Result<i64> {
+    id.parse()
+        .wrap_err_with(|| format!("Invalid sqlite event id: \"{id}\""))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before unix epoch")
+        .as_millis() as u64
+}
@@ -0,0 +1,79 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use eyre::eyre;
+use tokio::task::JoinHandle;
+
+use crate::{
+    context::Context,
+    utils::{backoff, with_spans},
+};
+
+/// Spawns the task and restarts it with the exponential backoff when it fails. The error is
+/// returned only after `max_restarts` failures in a row, which stops the whole service.
+pub fn spawn<F, Fut>(ctx: Arc<Context>, name: &str, task: F) -> JoinHandle<eyre::Result<()>>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    tokio::spawn(with_spans(name, supervise(ctx, name.to_owned(), task)))
+}
+
+async fn supervise<F, Fut>(ctx: Arc<Context>, name: String, task: F) -> eyre::Result<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    let cfg = ctx.config();
+    let max_backoff = Duration::from_millis(cfg.restart_max_backoff);
+    let mut failures = 0;
+    let mut restarts = 0;
+
+    loop {
+        let started = Instant::now();
+
+        let Err(e) = task().await else {
+            return Ok(());
+        };
+
+        if ctx.is_shutting_down() {
+            return Err(e);
+        }
+
+        // The task was running long enough, so the previous failures are not related
+        if started.elapsed() > max_backoff {
+            failures = 0;
+        }
+
+        failures += 1;
+        ctx.metrics().task_failed(&name, failures);
+
+        if failures >= cfg.max_restarts {
+            return Err(e.wrap_err(eyre!("Task has failed {failures} time(s) in a row")));
+        }
+
+        let backoff = backoff(
+            Duration::from_millis(cfg.restart_backoff),
+            failures,
+            max_backoff,
+        );
+        restarts += 1;
+
+        tracing::error!(
+            err = format!("{e:#}"),
+            restarts,
+            failures,
+            backoff_ms = backoff.as_millis() as u64,
+            "Task has failed, restarting..."
+        );
+
+        if !ctx.sleep(backoff).await {
+            return Ok(());
+        }
+
+        ctx.metrics().task_restarted(&name);
+    }
+}
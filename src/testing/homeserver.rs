@@ -0,0 +1,316 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{method, path, path_regex},
+    Mock, MockServer, Request, Respond, ResponseTemplate,
+};
+
+use crate::consts::MATRIX_USER_ID;
+
+/// Server name of the user and room IDs, the homeserver itself is served on a random local port.
+pub static SERVER_NAME: &str = "localhost";
+
+/// In-process stand-in of the Matrix homeserver, which serves the login, sync, hierarchy, state
+/// and join endpoints the relayer uses, and records the requests to check them in the tests.
+pub struct Homeserver {
+    server: MockServer,
+}
+
+impl Homeserver {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let homeserver = Self { server };
+
+        homeserver
+            .mount(
+                Mock::given(method("GET")).and(path("/_matrix/client/versions")),
+                json!({ "versions": ["v1.1", "v1.2", "v1.3", "v1.4", "v1.5", "v1.6"] }),
+            )
+            .await;
+
+        let session = json!({
+            "user_id": homeserver.relayer_id(),
+            "access_token": "relayer_token",
+            "device_id": "RELAYER",
+        });
+
+        homeserver
+            .mount(
+                Mock::given(method("POST")).and(path("/_matrix/client/v3/login")),
+                session.clone(),
+            )
+            .await;
+
+        homeserver
+            .mount(
+                Mock::given(method("GET")).and(path("/_matrix/client/v3/account/whoami")),
+                session,
+            )
+            .await;
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/v3/rooms/[^/]+/state/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$state" })))
+            .mount(&homeserver.server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path_regex(
+                r"^/_matrix/client/v3/(rooms/[^/]+/join|join/[^/]+)$",
+            ))
+            .respond_with(JoinResponder)
+            .mount(&homeserver.server)
+            .await;
+
+        homeserver
+    }
+
+    /// Base url of the homeserver, to be used as the `matrix_url`.
+    pub fn url(&self) -> String {
+        self.server.uri()
+    }
+
+    pub fn server_name(&self) -> String {
+        SERVER_NAME.to_owned()
+    }
+
+    pub fn relayer_id(&self) -> String {
+        format!("@{MATRIX_USER_ID}:{}", self.server_name())
+    }
+
+    pub fn room_id(&self, name: &str) -> String {
+        format!("!{name}:{}", self.server_name())
+    }
+
+    /// Responds to the sync with the rooms the relayer is joined to as an admin, and the rooms it
+    /// is invited to. The first room of `joined` is a space.
+    pub async fn sync(&self, joined: &[String], invited: &[String]) {
+        self.sync_with_power_levels(joined, invited, &[]).await;
+    }
+
+    /// Same as [`Homeserver::sync`], the users get the power levels in each of the joined rooms.
+    pub async fn sync_with_power_levels(
+        &self,
+        joined: &[String],
+        invited: &[String],
+        power_levels: &[(String, i64)],
+    ) {
+        let join: serde_json::Map<String, Value> = joined
+            .iter()
+            .enumerate()
+            .map(|(i, room_id)| {
+                let room = self.joined_room(room_id, i == 0, power_levels);
+                (room_id.clone(), room)
+            })
+            .collect();
+
+        let invite: serde_json::Map<String, Value> = invited
+            .iter()
+            .map(|room_id| (room_id.clone(), self.invited_room()))
+            .collect();
+
+        self.mount(
+            Mock::given(method("GET")).and(path("/_matrix/client/v3/sync")),
+            json!({
+                "next_batch": "s1",
+                "rooms": { "join": join, "invite": invite },
+            }),
+        )
+        .await;
+    }
+
+    /// Responds to the hierarchy request of the space with the space and its child rooms.
+    pub async fn hierarchy(&self, space_id: &str, children: &[String]) {
+        let rooms: Vec<Value> = std::iter::once(space_id)
+            .chain(children.iter().map(String::as_str))
+            .map(|room_id| {
+                json!({
+                    "room_id": room_id,
+                    "num_joined_members": 1,
+                    "world_readable": false,
+                    "guest_can_join": false,
+                    "children_state": [],
+                })
+            })
+            .collect();
+
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path_regex(r"^/_matrix/client/v1/rooms/[^/]+/hierarchy$")),
+            json!({ "rooms": rooms }),
+        )
+        .await;
+    }
+
+    /// Returns the room ids and the contents of the state events of the type sent by the relayer.
+    pub async fn state_events(&self, event_type: &str) -> Vec<(String, Value)> {
+        self.requests("PUT")
+            .await
+            .into_iter()
+            .filter_map(|(segments, body)| {
+                let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+                match segments.as_slice() {
+                    ["", "_matrix", "client", "v3", "rooms", room_id, "state", kind, ..]
+                        if *kind == event_type =>
+                    {
+                        Some((room_id.to_string(), body))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the ids of the rooms the relayer has joined.
+    pub async fn joined_rooms(&self) -> Vec<String> {
+        self.requests("POST")
+            .await
+            .into_iter()
+            .filter_map(|(segments, _)| {
+                let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+                join_room_id(&segments).map(str::to_owned)
+            })
+            .collect()
+    }
+
+    async fn requests(&self, http_method: &str) -> Vec<(Vec<String>, Value)> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|req| req.method.as_str() == http_method)
+            .map(|req| {
+                (
+                    decoded_segments(&req),
+                    req.body_json().unwrap_or(Value::Null),
+                )
+            })
+            .collect()
+    }
+
+    async fn mount(&self, mock: wiremock::MockBuilder, body: Value) {
+        mock.respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// The room with the relayer as an admin, the users with the power levels are joined members.
+    fn joined_room(&self, room_id: &str, space: bool, power_levels: &[(String, i64)]) -> Value {
+        let relayer_id = self.relayer_id();
+        let mut create = json!({ "creator": relayer_id, "room_version": "10" });
+
+        if space {
+            create["type"] = json!("m.space");
+        }
+
+        let mut users = json!({ relayer_id.clone(): 100 });
+        let mut events = vec![state_event(
+            room_id,
+            "m.room.create",
+            "",
+            &relayer_id,
+            create,
+        )];
+
+        for user_id in std::iter::once(&relayer_id).chain(power_levels.iter().map(|(id, _)| id)) {
+            events.push(state_event(
+                room_id,
+                "m.room.member",
+                user_id,
+                user_id,
+                json!({ "membership": "join" }),
+            ));
+        }
+
+        for (user_id, level) in power_levels {
+            users[user_id] = json!(level);
+        }
+
+        events.push(state_event(
+            room_id,
+            "m.room.power_levels",
+            "",
+            &relayer_id,
+            json!({
+                "users": users,
+                "users_default": 0,
+                "events_default": 0,
+                "state_default": 50,
+                "ban": 50,
+                "kick": 50,
+                "invite": 0,
+                "redact": 50,
+                "events": {},
+            }),
+        ));
+
+        json!({
+            "state": { "events": events },
+            "timeline": { "events": [], "limited": false },
+        })
+    }
+
+    fn invited_room(&self) -> Value {
+        json!({
+            "invite_state": {
+                "events": [{
+                    "type": "m.room.member",
+                    "state_key": self.relayer_id(),
+                    "sender": format!("@admin:{}", self.server_name()),
+                    "content": { "membership": "invite" },
+                }],
+            },
+        })
+    }
+}
+
+/// Echoes the room id of the join request, as the homeserver does.
+struct JoinResponder;
+
+impl Respond for JoinResponder {
+    fn respond(&self, req: &Request) -> ResponseTemplate {
+        let segments = decoded_segments(req);
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let room_id = join_room_id(&segments).unwrap_or_default();
+
+        ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id }))
+    }
+}
+
+fn join_room_id<'a>(segments: &[&'a str]) -> Option<&'a str> {
+    match segments {
+        [.., "rooms", room_id, "join"] | [.., "join", room_id] => Some(room_id),
+        _ => None,
+    }
+}
+
+fn state_event(room_id: &str, kind: &str, state_key: &str, sender: &str, content: Value) -> Value {
+    // Unique per state key, so the member events of the room don't replace each other
+    let mut hasher = DefaultHasher::new();
+    (room_id, kind, state_key).hash(&mut hasher);
+
+    json!({
+        "type": kind,
+        "state_key": state_key,
+        "sender": sender,
+        "content": content,
+        "event_id": format!("${:016x}", hasher.finish()),
+        "origin_server_ts": 0,
+    })
+}
+
+/// Path segments of the request, the room ids are percent-encoded by the client.
+fn decoded_segments(req: &Request) -> Vec<String> {
+    req.url
+        .path()
+        .split('/')
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect()
+}
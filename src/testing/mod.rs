@@ -0,0 +1,78 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use matrix_sdk::config::SyncSettings;
+use serde_json::json;
+use tempfile::TempDir;
+
+use crate::{config::Config, context::Context, icp::IcpApi, matrix, metrics::Metrics};
+
+mod homeserver;
+
+pub use homeserver::Homeserver;
+
+/// Config of the offline tests: in-memory store, short intervals and an unreachable homeserver.
+/// The matrix store is kept in the directory, so it has to outlive the context.
+pub fn config(dir: &TempDir) -> Config {
+    let matrix_store_path = dir.path().join("matrix-store");
+
+    serde_json::from_value(json!({
+        "proxy_id": "24swh-4iaaa-aaaap-ahevq-cai",
+        "history_id": "qejor-xqaaa-aaaap-ahjaa-cai",
+        "store": "memory",
+        "matrix_url": "http://localhost:8008",
+        "matrix_server_name": homeserver::SERVER_NAME,
+        "matrix_store_path": matrix_store_path,
+        "interval": 10,
+        "claim_timeout": 0,
+    }))
+    .expect("Failed to deserialize test config")
+}
+
+/// Context with the mock ICP API, the matrix client is created but not logged in.
+pub async fn context(cfg: Config, icp: Arc<dyn IcpApi>) -> Arc<Context> {
+    let metrics = Metrics::new().expect("Failed to create metrics");
+
+    Context::with_icp(cfg, metrics, icp)
+        .await
+        .expect("Failed to create test context")
+}
+
+/// Context logged in to the fake homeserver and synced with its rooms.
+pub async fn synced_context(
+    homeserver: &Homeserver,
+    mut cfg: Config,
+    icp: Arc<dyn IcpApi>,
+) -> Arc<Context> {
+    cfg.matrix_url = homeserver.url();
+    cfg.password = "password".to_owned();
+
+    let ctx = context(cfg, icp).await;
+
+    matrix::authorize(ctx.clone())
+        .await
+        .expect("Failed to log in to the fake homeserver");
+
+    ctx.matrix()
+        .sync_once(SyncSettings::default())
+        .await
+        .expect("Failed to sync with the fake homeserver");
+
+    ctx
+}
+
+/// Waits until the condition is met, panics after a few seconds.
+pub async fn eventually<F, Fut>(condition: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let wait = async {
+        while !condition().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("Condition is not met in time");
+}
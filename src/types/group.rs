@@ -0,0 +1,63 @@
+use candid::Principal;
+use proxy_types::models::{
+    asset::Asset, group::GroupResponse, member::JoinedMemberResponse, paged_response::PagedResponse,
+};
+
+/// Group of the proxy canister with the fields the relayer uses, so the canister types don't leak
+/// into the consumers and the tests can script the groups.
+#[derive(Debug, Clone, Default)]
+pub struct Group {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    /// Url of the group image, `None` if the image is not an url.
+    pub image_url: Option<String>,
+    pub matrix_space_id: String,
+}
+
+impl From<GroupResponse> for Group {
+    fn from(group: GroupResponse) -> Self {
+        let image_url = match group.image {
+            Asset::Url(url) if !url.is_empty() => Some(url),
+            _ => None,
+        };
+
+        Self {
+            id: group.id,
+            name: group.name,
+            description: group.description,
+            image_url,
+            matrix_space_id: group.matrix_space_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupMember {
+    pub principal: Principal,
+    pub roles: Vec<String>,
+}
+
+impl From<JoinedMemberResponse> for GroupMember {
+    fn from(member: JoinedMemberResponse) -> Self {
+        Self {
+            principal: member.principal,
+            roles: member.roles,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GroupsPage {
+    pub groups: Vec<Group>,
+    pub number_of_pages: u64,
+}
+
+impl From<PagedResponse<GroupResponse>> for GroupsPage {
+    fn from(page: PagedResponse<GroupResponse>) -> Self {
+        Self {
+            groups: page.data.into_iter().map(Group::from).collect(),
+            number_of_pages: page.number_of_pages as u64,
+        }
+    }
+}
@@ -1,35 +1,44 @@
 use std::fmt::Display;
 
 use candid::Principal;
+use eyre::Context as _;
+use matrix_sdk::ruma::{OwnedUserId, UserId};
 
 #[derive(Debug, Clone)]
 pub struct MatrixUserID {
     pub principal: Principal,
     pub username: String,
-    pub matrix_base_url: String,
+    pub server_name: String,
 }
 
 impl MatrixUserID {
-    pub fn new(principal: Principal, username: String, matrix_base_url: String) -> Self {
+    pub fn new(principal: Principal, username: String, server_name: String) -> Self {
         Self {
             principal,
             username,
-            matrix_base_url,
+            server_name,
         }
     }
+
+    pub fn to_user_id(&self) -> eyre::Result<OwnedUserId> {
+        UserId::parse(self.to_string()).wrap_err_with(|| format!("Failed to parse user id: {self}"))
+    }
 }
 
 impl Display for MatrixUserID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let principal = self.principal.to_string();
         let username = self.username.to_lowercase();
-        let matrix_url = self
-            .matrix_base_url
-            .trim_start_matches("https://matrix.")
-            .trim_start_matches("https://") // You ask me why? - I don't know
-            .trim_end_matches('/');
 
         // Equal to how the front-end deterministically generates the user ID for the matrix
-        write!(f, "@{principal}/{username}:{matrix_url}")
+        write!(f, "@{principal}/{username}:{}", self.server_name)
     }
 }
+
+/// Server name part of the user IDs on the matrix server with the given base url.
+pub fn server_name(matrix_base_url: &str) -> &str {
+    matrix_base_url
+        .trim_start_matches("https://matrix.")
+        .trim_start_matches("https://") // You ask me why? - I don't know
+        .trim_end_matches('/')
+}
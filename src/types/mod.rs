@@ -1,7 +1,9 @@
+mod group;
 mod matrix_user_id;
 mod result;
 mod role;
 
+pub use group::*;
 pub use matrix_user_id::*;
 pub use result::*;
 pub use role::*;
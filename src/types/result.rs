@@ -1,7 +1,27 @@
+use std::fmt::Display;
+
 use candid::{CandidType, Decode};
 use proxy_types::models::api_error::ApiError;
 use serde::Deserialize;
 
+/// Error the canister has responded with, e.g. the requested group doesn't exist. Unlike the
+/// errors of the call itself, it won't go away on a retry.
+#[derive(Debug)]
+pub struct CanisterError(pub String);
+
+impl Display for CanisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CanisterError {}
+
+/// Returns true if the canister has responded with the error, see [`CanisterError`].
+pub fn is_canister_error(e: &eyre::Report) -> bool {
+    e.chain().any(|e| e.is::<CanisterError>())
+}
+
 #[derive(CandidType, Deserialize)]
 pub enum CanisterResult<T> {
     Ok(T),
@@ -26,7 +46,7 @@ impl<'de, T: CandidType + Deserialize<'de>> CanisterResult<T> {
     pub fn into_result(self) -> eyre::Result<T> {
         match self {
             CanisterResult::Ok(result) => Ok(result),
-            CanisterResult::Err(err) => Err(eyre::eyre!("{:#?}", err)),
+            CanisterResult::Err(err) => Err(CanisterError(format!("{:#?}", err)).into()),
         }
     }
 }
@@ -1,33 +1,201 @@
-use std::str::FromStr;
+use std::collections::HashMap;
 
-use eyre::bail;
+use serde::{de::Error as _, Deserialize, Deserializer};
 
-#[derive(Debug, Clone)]
-pub enum Role {
-    Owner = 100,
-    Admin = 95,
-    Moderator = 50,
-    Member = 10,
+use crate::config::Config;
+
+/// Power levels of the built-in roles of the proxy canister.
+pub fn default_role_power_levels() -> HashMap<String, u64> {
+    HashMap::from([
+        ("owner".to_owned(), 100),
+        ("admin".to_owned(), 95),
+        ("moderator".to_owned(), 50),
+        ("member".to_owned(), 10),
+    ])
 }
 
-impl FromStr for Role {
-    type Err = eyre::Report;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "owner" => Ok(Self::Owner),
-            "admin" => Ok(Self::Admin),
-            "moderator" => Ok(Self::Moderator),
-            "member" => Ok(Self::Member),
-            _ => {
-                bail!("Invalid role: {}", s)
-            }
+/// Returns the power level of the role in the group. The overrides of the group go first, then
+/// the `role_power_levels`, and the `default_power_level` for the roles which are not mapped.
+/// The proxy canister gives no power level to the custom roles of a group, so they are mapped by
+/// their names like the built-in ones. Role names are case-insensitive.
+pub fn role_power_level(cfg: &Config, group_id: u64, role: &str) -> Option<u64> {
+    let role = role.to_lowercase();
+
+    cfg.group_role_power_levels
+        .get(&group_id.to_string())
+        .and_then(|levels| levels.get(&role))
+        .or_else(|| cfg.role_power_levels.get(&role))
+        .copied()
+        .or(cfg.default_power_level)
+}
+
+/// Deserializes the `role_power_levels` with the lowercase role names.
+pub fn deserialize_role_power_levels<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let levels = HashMap::<String, u64>::deserialize(deserializer)?;
+    lowercase_roles(levels).map_err(D::Error::custom)
+}
+
+/// Deserializes the `group_role_power_levels` with the lowercase role names.
+pub fn deserialize_group_role_power_levels<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, HashMap<String, u64>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, HashMap<String, u64>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(group_id, levels)| {
+            let levels = lowercase_roles(levels)
+                .map_err(|e| D::Error::custom(format!("{e} in the group {group_id}")))?;
+            Ok((group_id, levels))
+        })
+        .collect()
+}
+
+/// Lowercases the role names, rejects the names which differ only in case, as it is not clear
+/// which of their power levels applies.
+fn lowercase_roles(levels: HashMap<String, u64>) -> Result<HashMap<String, u64>, String> {
+    let mut lowercased = HashMap::with_capacity(levels.len());
+
+    for (role, level) in levels {
+        if lowercased.insert(role.to_lowercase(), level).is_some() {
+            return Err(format!(
+                "Role \"{role}\" is mapped more than once, role names are case-insensitive"
+            ));
         }
     }
+
+    Ok(lowercased)
+}
+
+/// Returns the power level of the member with the roles: the level of the first role of
+/// `role_precedence` the member has, or the highest level of the member roles otherwise.
+pub fn resolve_power_level(cfg: &Config, group_id: u64, roles: &[String]) -> Option<u64> {
+    let mapped: Vec<(&str, u64)> = roles
+        .iter()
+        .filter_map(|role| Some((role.as_str(), role_power_level(cfg, group_id, role)?)))
+        .collect();
+
+    let preferred = cfg.role_precedence.iter().find_map(|preferred| {
+        mapped
+            .iter()
+            .find(|(role, _)| role.to_lowercase() == preferred.to_lowercase())
+    });
+
+    let (strategy, resolved) = match preferred {
+        Some(resolved) => ("precedence", Some(resolved)),
+        None => ("highest", mapped.iter().max_by_key(|(_, level)| *level)),
+    };
+
+    let Some((role, power_level)) = resolved else {
+        tracing::warn!(
+            group_id,
+            roles = roles.join(", "),
+            "No power level is mapped to any of the roles"
+        );
+        return None;
+    };
+
+    tracing::info!(
+        group_id,
+        roles = roles.join(", "),
+        strategy,
+        role,
+        power_level,
+        "Resolved power level of the roles"
+    );
+
+    Some(*power_level)
 }
 
-impl Role {
-    pub fn power_level(&self) -> u64 {
-        self.clone() as u64
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_role_power_level() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        cfg.role_power_levels.insert("curator".to_owned(), 30);
+        cfg.group_role_power_levels.insert(
+            "7".to_owned(),
+            HashMap::from([("moderator".to_owned(), 60)]),
+        );
+
+        assert_eq!(role_power_level(&cfg, 1, "Owner"), Some(100));
+        assert_eq!(role_power_level(&cfg, 1, "curator"), Some(30));
+        assert_eq!(role_power_level(&cfg, 1, "moderator"), Some(50));
+        assert_eq!(role_power_level(&cfg, 7, "moderator"), Some(60));
+        assert_eq!(role_power_level(&cfg, 7, "admin"), Some(95));
+        assert_eq!(role_power_level(&cfg, 1, "unknown"), None);
+
+        cfg.default_power_level = Some(5);
+        assert_eq!(role_power_level(&cfg, 1, "unknown"), Some(5));
+    }
+
+    #[test]
+    fn test_lowercase_roles() {
+        let levels = lowercase_roles(HashMap::from([("Curator".to_owned(), 30)])).unwrap();
+        assert_eq!(levels, HashMap::from([("curator".to_owned(), 30)]));
+
+        let duplicates = HashMap::from([("Curator".to_owned(), 30), ("curator".to_owned(), 40)]);
+        assert!(lowercase_roles(duplicates).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_role_power_levels() {
+        #[derive(Deserialize)]
+        struct Levels {
+            #[serde(deserialize_with = "deserialize_role_power_levels")]
+            roles: HashMap<String, u64>,
+            #[serde(deserialize_with = "deserialize_group_role_power_levels")]
+            groups: HashMap<String, HashMap<String, u64>>,
+        }
+
+        let levels: Levels = serde_json::from_value(serde_json::json!({
+            "roles": { "Curator": 30 },
+            "groups": { "7": { "Moderator": 60 } },
+        }))
+        .unwrap();
+        assert_eq!(levels.roles["curator"], 30);
+        assert_eq!(levels.groups["7"]["moderator"], 60);
+
+        let duplicates = serde_json::from_value::<Levels>(serde_json::json!({
+            "roles": {},
+            "groups": { "7": { "Admin": 90, "admin": 95 } },
+        }));
+        assert!(duplicates.is_err());
+    }
+
+    #[test]
+    fn test_resolve_power_level() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = testing::config(&dir);
+        let roles = |roles: &[&str]| roles.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(resolve_power_level(&cfg, 1, &roles(&[])), None);
+        assert_eq!(resolve_power_level(&cfg, 1, &roles(&["unknown"])), None);
+        assert_eq!(
+            resolve_power_level(&cfg, 1, &roles(&["member", "unknown", "admin"])),
+            Some(95)
+        );
+
+        cfg.role_precedence = roles(&["Moderator", "admin"]);
+        assert_eq!(
+            resolve_power_level(&cfg, 1, &roles(&["admin", "moderator"])),
+            Some(50)
+        );
+        assert_eq!(
+            resolve_power_level(&cfg, 1, &roles(&["owner", "member"])),
+            Some(100)
+        );
     }
 }
@@ -0,0 +1,13 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Doubles the base delay on each attempt up to the max and adds up to a half of it as a jitter,
+/// so the replicas don't hammer the recovered dependency at the same time.
+pub fn backoff(base: Duration, attempt: u32, max: Duration) -> Duration {
+    let exp = base.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let delay = exp.min(max);
+    let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+
+    delay + Duration::from_millis(jitter)
+}
@@ -0,0 +1,163 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    HalfOpen,
+    Open,
+}
+
+/// Fails the calls fast after `threshold` failures in a row, until `timeout` passes. Then the
+/// circuit is half-open: a single probe call is let through and closes the circuit if it succeeds,
+/// the concurrent calls keep failing fast until the probe completes.
+pub struct CircuitBreaker {
+    threshold: u32,
+    timeout: Duration,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, timeout: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            timeout,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let inner = self.lock();
+
+        match inner.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() >= self.timeout => CircuitState::HalfOpen,
+            Some(_) => CircuitState::Open,
+        }
+    }
+
+    /// Returns the time left until the circuit is half-open, `None` if the call can be made.
+    pub fn retry_in(&self) -> Option<Duration> {
+        let inner = self.lock();
+        let opened_at = inner.opened_at?;
+
+        self.timeout.checked_sub(opened_at.elapsed())
+    }
+
+    /// Returns the time left until the call can be made, `None` if it is let through. Takes the
+    /// probe slot of the half-open circuit, which is freed by `success`, `failure` or `release`.
+    pub fn try_acquire(&self) -> Option<Duration> {
+        let mut inner = self.lock();
+        let opened_at = inner.opened_at?;
+
+        if let Some(retry_in) = self.timeout.checked_sub(opened_at.elapsed()) {
+            return Some(retry_in);
+        }
+
+        // The probe of a dropped call never completes, so the slot expires after the timeout
+        if let Some(retry_in) = inner
+            .probe_started_at
+            .and_then(|started_at| self.timeout.checked_sub(started_at.elapsed()))
+        {
+            return Some(retry_in);
+        }
+
+        inner.probe_started_at = Some(Instant::now());
+        None
+    }
+
+    pub fn success(&self) {
+        *self.lock() = Inner::default();
+    }
+
+    pub fn failure(&self) {
+        let mut inner = self.lock();
+        inner.failures += 1;
+        inner.probe_started_at = None;
+
+        // The failed call of the half-open circuit opens it again for the whole timeout
+        if inner.failures >= self.threshold {
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// Frees the probe slot without counting the call, e.g. when the canister rejected it.
+    pub fn release(&self) {
+        self.lock().probe_started_at = None;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("Circuit breaker lock is poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let circuit = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        circuit.failure();
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.retry_in().is_none());
+
+        circuit.failure();
+        assert_eq!(circuit.state(), CircuitState::Open);
+        assert!(circuit.retry_in().is_some());
+
+        circuit.success();
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_half_open_after_timeout() {
+        let circuit = CircuitBreaker::new(1, Duration::ZERO);
+
+        circuit.failure();
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.retry_in().is_none());
+    }
+
+    #[test]
+    fn test_half_open_lets_one_probe_through() {
+        let circuit = CircuitBreaker::new(1, Duration::from_millis(50));
+
+        circuit.failure();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+
+        assert!(circuit.try_acquire().is_none());
+        assert!(circuit.try_acquire().is_some());
+
+        circuit.release();
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.try_acquire().is_none());
+
+        circuit.success();
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.try_acquire().is_none());
+        assert!(circuit.try_acquire().is_none());
+    }
+
+    #[test]
+    fn test_release_keeps_failures() {
+        let circuit = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        circuit.failure();
+        circuit.release();
+        circuit.failure();
+
+        assert_eq!(circuit.state(), CircuitState::Open);
+    }
+}
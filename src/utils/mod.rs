@@ -1,4 +1,9 @@
+mod backoff;
+mod circuit;
 mod span;
+pub mod trace;
 mod tracing;
+pub use backoff::*;
+pub use circuit::*;
 pub use span::*;
 pub use tracing::*;
@@ -2,12 +2,11 @@ use std::future::Future;
 
 use tracing::{instrument::Instrumented, Instrument};
 
+/// Instruments the long running task with the `runner` span, the error level keeps the span
+/// enabled with any log filter.
 pub fn with_spans<T>(
     name: &str,
     fut: impl Future<Output = T>,
 ) -> Instrumented<impl Future<Output = T>> {
-    fut.instrument(tracing::debug_span!("runner", name))
-        .instrument(tracing::info_span!("runner", name))
-        .instrument(tracing::error_span!("runner", name))
-        .instrument(tracing::warn_span!("runner", name))
+    fut.instrument(tracing::error_span!("runner", name))
 }
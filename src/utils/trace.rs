@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use tracing::Span;
+
+/// W3C trace context of the history event, queued alongside the event so the consumer continues
+/// the trace started by the producer. Empty without the `otel` feature.
+pub type TraceContext = HashMap<String, String>;
+
+/// Returns the trace context of the span.
+pub fn inject(span: &Span) -> TraceContext {
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let mut trace = TraceContext::new();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&span.context(), &mut trace)
+        });
+        trace
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = span;
+        TraceContext::new()
+    }
+}
+
+/// Makes the span a child of the span the trace context was injected from.
+pub fn set_parent(span: &Span, trace: &TraceContext) {
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        if trace.is_empty() {
+            return;
+        }
+
+        let parent =
+            opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(trace));
+        span.set_parent(parent);
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = (span, trace);
+    }
+}
@@ -1,14 +1,81 @@
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-pub fn init_tracing(log_filter: String) {
+/// Flushes the spans which are not exported yet on drop.
+pub struct TracingGuard;
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Initializes the stdout logs, and the OTLP export to `otel_endpoint` with the `otel` feature.
+pub fn init_tracing(
+    log_filter: String,
+    otel_endpoint: Option<String>,
+) -> eyre::Result<TracingGuard> {
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_ansi(cfg!(debug_assertions))
         .with_target(false);
 
     let filter_layer = EnvFilter::new(log_filter);
 
-    tracing_subscriber::registry()
+    let registry = tracing_subscriber::registry()
         .with(filter_layer)
-        .with(fmt_layer)
-        .init();
+        .with(fmt_layer);
+
+    #[cfg(feature = "otel")]
+    {
+        let otel_layer = otel_endpoint.as_deref().map(otel::layer).transpose()?;
+        registry.with(otel_layer).init();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+
+        if otel_endpoint.is_some() {
+            tracing::warn!(
+                "OTLP endpoint is set, but the relayer is built without \"otel\" feature"
+            );
+        }
+    }
+
+    Ok(TracingGuard)
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use eyre::Context as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+    use tracing::Subscriber;
+    use tracing_subscriber::{registry::LookupSpan, Layer};
+
+    static SERVICE_NAME: &str = "relayer";
+
+    pub fn layer<S>(endpoint: &str) -> eyre::Result<impl Layer<S>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let tracer =
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", SERVICE_NAME),
+                ])))
+                .install_batch(runtime::Tokio)
+                .wrap_err_with(|| format!("Failed to install OTLP exporter to \"{endpoint}\""))?;
+
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
 }